@@ -0,0 +1,97 @@
+//! Timings for the logging, rendering and completion hot paths.
+//!
+//! Run with `cargo bench`, optionally followed by `-- <filter>` to run only
+//! the benchmarks whose name contains `<filter>`. Compare the per-iteration
+//! numbers before and after a change.
+
+use ratatui::{backend::TestBackend, Terminal};
+use riege_xterm::core::repl_new::{completion_candidates, COMPLETION_CANDIDATES};
+use riege_xterm::core::ui::{Completion, TerminalUI};
+use std::hint::black_box;
+use std::time::Instant;
+
+const PANE_WIDTH: u16 = 200;
+const PANE_HEIGHT: u16 = 60;
+
+fn bench<F: FnMut()>(filter: Option<&str>, name: &str, iterations: u32, mut f: F) {
+    if filter.is_some_and(|filter| !name.contains(filter)) {
+        return;
+    }
+
+    // Warm-up, so allocations on first use aren't counted.
+    f();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let per_iteration = start.elapsed() / iterations;
+    println!("{:<32} {:>12.3?} per iteration ({} iterations)", name, per_iteration, iterations);
+}
+
+// A full scrollback with a realistic mix of levels, ANSI codes and long
+// lines.
+fn filled_ui() -> TerminalUI {
+    let ui = TerminalUI::new();
+    let logger = ui.get_message_logger();
+    for i in 0..1000 {
+        match i % 5 {
+            0 => logger.info(&format!("Loaded chunk {} in {}ms", i, i % 17)),
+            1 => logger.warning(&format!("Slow tick: {}ms behind", i % 50)),
+            2 => logger.error(&format!("\x1b[31mConnection reset\x1b[0m (attempt {})", i)),
+            3 => logger.log(format!("{} {}", "long line".repeat(30), i)),
+            _ => logger.log(format!("plain output {}", i)),
+        };
+    }
+    ui
+}
+
+fn render(terminal: &mut Terminal<TestBackend>, ui: &TerminalUI) {
+    terminal.draw(|f| ui.draw(f)).unwrap();
+    black_box(terminal.backend().buffer());
+}
+
+fn main() {
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with("--"));
+    let filter = filter.as_deref();
+
+    bench(filter, "log_100k_lines", 5, || {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        for i in 0..100_000 {
+            black_box(logger.info(&format!("line {}", i)));
+        }
+    });
+
+    let mut terminal = Terminal::new(TestBackend::new(PANE_WIDTH, PANE_HEIGHT)).unwrap();
+
+    // Baseline: the cost of a frame with nothing in the message pane.
+    let empty = TerminalUI::new();
+    bench(filter, "render_empty", 200, || render(&mut terminal, &empty));
+
+    // A static view, as while scrolled up with no new output.
+    let ui = filled_ui();
+    bench(filter, "render_full_pane", 200, || render(&mut terminal, &ui));
+
+    // A new line before every frame, so nothing can be reused.
+    let logger = ui.get_message_logger();
+    bench(filter, "render_full_pane_after_log", 200, || {
+        logger.info("tick");
+        render(&mut terminal, &ui);
+    });
+
+    let mut ui = filled_ui();
+    ui.set_word_wrap(true);
+    bench(filter, "render_full_pane_wrapped", 200, || render(&mut terminal, &ui));
+
+    let logger = ui.get_message_logger();
+    bench(filter, "render_full_pane_wrapped_after_log", 200, || {
+        logger.info("tick");
+        render(&mut terminal, &ui);
+    });
+
+    *COMPLETION_CANDIDATES.lock().unwrap() =
+        (0..1000).map(|i| Completion::new(format!("/command-{:04} --option", i), "Runs a command")).collect();
+    bench(filter, "completion_clone_1k", 10_000, || {
+        black_box(completion_candidates());
+    });
+}
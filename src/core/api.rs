@@ -1,15 +1,63 @@
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_char};
-use std::sync::atomic::Ordering;
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use ratatui::layout::Alignment;
+use ratatui::style::{Color, Style};
+use crate::core::locks::{self, Rank};
 use crate::core::logger;
+use crate::core::ui::{Completion, Level, LogStatus, TransformedInput};
 use crate::core::repl_new::{
     SHUTDOWN_SIGNAL,
     JAVA_INPUT_CALLBACK,
     JAVA_TAB_CALLBACK,
+    JAVA_KEY_CALLBACK,
+    JAVA_KEYPRESS_CALLBACK,
+    JAVA_TRANSFORM_CALLBACK,
+    JAVA_HEARTBEAT_CALLBACK,
+    HEARTBEAT_INTERVAL_MS,
+    TRANSFORMED_INPUT,
+    HISTORY_FILE,
     COMPLETION_CANDIDATES,
+    BANNER_ENABLED,
+    FINAL_MESSAGES,
+    TERMINAL_RUNNING,
+    PLACEHOLDER,
+    show_banner,
     Terminal
 };
 
+// Bumped on every change that breaks existing callers: a function removed
+// or renamed, a changed signature, callback type or return code. Additions
+// don't count.
+const ABI_VERSION: u32 = 1;
+
+/// Version of this library's C interface. Callers built against a newer
+/// one than they find should not rely on anything added since.
+#[no_mangle]
+pub extern "C" fn terminal_abi_version() -> u32 {
+    ABI_VERSION
+}
+
+/// Copies the crate version, e.g. "0.1.0", into `buf` as a NUL-terminated
+/// string, truncated to fit `len` bytes. Returns the version's full length
+/// without the NUL, like `snprintf`, so `buf` may be null with `len` 0 to
+/// ask how much room it needs.
+#[no_mangle]
+pub extern "C" fn terminal_version_string(buf: *mut c_char, len: usize) -> c_int {
+    let version = env!("CARGO_PKG_VERSION").as_bytes();
+    if !buf.is_null() && len > 0 {
+        let copied = version.len().min(len - 1);
+        unsafe {
+            std::ptr::copy_nonoverlapping(version.as_ptr(), buf.cast::<u8>(), copied);
+            *buf.add(copied) = 0;
+        }
+    }
+    version.len() as c_int
+}
+
 #[no_mangle]
 pub extern "C" fn terminal_log_info(msg: *const c_char) {
     if msg.is_null() { return; }
@@ -60,6 +108,365 @@ pub extern "C" fn terminal_log_debug(msg: *const c_char) {
     }
 }
 
+/// Logs `msg` in the given RGB color. The color wins over the one a level
+/// prefix like `[ERROR]` would give, and `[RUSTn]` tags are kept as text.
+#[no_mangle]
+pub extern "C" fn terminal_log_rgb(r: u8, g: u8, b: u8, msg: *const c_char) {
+    if msg.is_null() { return; }
+    unsafe {
+        if let Ok(c_str) = CStr::from_ptr(msg).to_str() {
+            logger::log_styled(c_str.to_string(), Style::default().fg(Color::Rgb(r, g, b)));
+        }
+    }
+}
+
+/// Logs `msg` after a `[label]` tag in the label's color, as set with
+/// `terminal_set_label_color`. Labels naming a level (`ERROR`, `INFO`, ...)
+/// count as that level.
+#[no_mangle]
+pub extern "C" fn terminal_log_labeled(label: *const c_char, msg: *const c_char) {
+    if label.is_null() || msg.is_null() { return; }
+    unsafe {
+        if let (Ok(label), Ok(msg)) = (CStr::from_ptr(label).to_str(), CStr::from_ptr(msg).to_str()) {
+            logger::log_labeled(label, msg.to_string());
+        }
+    }
+}
+
+/// Logs `msg` aligned in the message pane: 0 left, 1 centered, 2 right
+/// (anything else is left). Returns a LogStatus code like the `_checked`
+/// variants.
+#[no_mangle]
+pub extern "C" fn terminal_log_aligned(align: c_int, msg: *const c_char) -> c_int {
+    let alignment = match align {
+        1 => Alignment::Center,
+        2 => Alignment::Right,
+        _ => Alignment::Left,
+    };
+    if msg.is_null() { return LogStatus::Dropped as c_int; }
+    unsafe {
+        match CStr::from_ptr(msg).to_str() {
+            Ok(c_str) => logger::log_aligned(c_str.to_string(), alignment) as c_int,
+            Err(_) => LogStatus::Dropped as c_int,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_set_label_color(label: *const c_char, r: u8, g: u8, b: u8) {
+    if label.is_null() { return; }
+    unsafe {
+        if let Ok(label) = CStr::from_ptr(label).to_str() {
+            logger::set_label_color(label, Color::Rgb(r, g, b));
+        }
+    }
+}
+
+/// Logs `msg` as a pinned line, which is never evicted when the buffer is
+/// full (only cleared). `level` picks the prefix: 0 none, 1 info,
+/// 2 success, 3 warning, 4 error, 5 debug. Returns a LogStatus code like
+/// the `_checked` variants.
+#[no_mangle]
+pub extern "C" fn terminal_log_pinned(level: c_int, msg: *const c_char) -> c_int {
+    let level = match level {
+        1 => Some(Level::Info),
+        2 => Some(Level::Success),
+        3 => Some(Level::Warning),
+        4 => Some(Level::Error),
+        5 => Some(Level::Debug),
+        _ => None,
+    };
+    if msg.is_null() { return LogStatus::Dropped as c_int; }
+    unsafe {
+        match CStr::from_ptr(msg).to_str() {
+            Ok(c_str) => logger::log_pinned(level, c_str.to_string()) as c_int,
+            Err(_) => LogStatus::Dropped as c_int,
+        }
+    }
+}
+
+// The `_checked` variants return a LogStatus code: 0 = accepted,
+// 1 = accepted but older lines were evicted (buffer full), 2 = dropped.
+fn log_checked(msg: *const c_char, log_fn: fn(&str) -> LogStatus) -> c_int {
+    if msg.is_null() { return LogStatus::Dropped as c_int; }
+    unsafe {
+        match CStr::from_ptr(msg).to_str() {
+            Ok(c_str) => log_fn(c_str) as c_int,
+            Err(_) => LogStatus::Dropped as c_int,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_log_info_checked(msg: *const c_char) -> c_int {
+    log_checked(msg, logger::info)
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_log_error_checked(msg: *const c_char) -> c_int {
+    log_checked(msg, logger::error)
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_log_success_checked(msg: *const c_char) -> c_int {
+    log_checked(msg, logger::success)
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_log_warning_checked(msg: *const c_char) -> c_int {
+    log_checked(msg, logger::warning)
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_log_debug_checked(msg: *const c_char) -> c_int {
+    log_checked(msg, logger::debug)
+}
+
+/// Logs a line that can be rewritten in place with `terminal_update_message`,
+/// e.g. a progress bar such as "[####----] 50%". Returns its id, or 0 if
+/// it wasn't stored (including before `terminal_start`).
+#[no_mangle]
+pub extern "C" fn terminal_log_updatable(msg: *const c_char) -> u64 {
+    if msg.is_null() { return 0; }
+    unsafe {
+        match CStr::from_ptr(msg).to_str() {
+            Ok(c_str) => logger::log_updatable(c_str).unwrap_or(0),
+            Err(_) => 0,
+        }
+    }
+}
+
+/// Starts a block of output that is shown collapsed under a
+/// "▸ label (N lines)" header; lines logged until `terminal_fold_end` go
+/// into it. F6 expands or collapses all blocks. Returns the block's id, or
+/// 0 if it wasn't started (including before `terminal_start`).
+#[no_mangle]
+pub extern "C" fn terminal_fold_begin(label: *const c_char) -> u64 {
+    if label.is_null() { return 0; }
+    unsafe {
+        match CStr::from_ptr(label).to_str() {
+            Ok(c_str) => logger::fold_begin(c_str).unwrap_or(0),
+            Err(_) => 0,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_fold_end() {
+    logger::fold_end();
+}
+
+/// Expands (non-zero) or collapses (0) the block `id`.
+#[no_mangle]
+pub extern "C" fn terminal_fold_set_expanded(id: u64, expanded: c_int) {
+    logger::set_fold_expanded(id, expanded != 0);
+}
+
+/// Replaces the text of line `id` without moving it or the view. The last
+/// update stays in the scrollback. Returns 0 on success, -1 if the line
+/// has been evicted or cleared.
+#[no_mangle]
+pub extern "C" fn terminal_update_message(id: u64, msg: *const c_char) -> c_int {
+    if msg.is_null() { return -1; }
+    unsafe {
+        match CStr::from_ptr(msg).to_str() {
+            Ok(c_str) if logger::update(id, c_str) => 0,
+            _ => -1,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_insert_message(index: usize, msg: *const c_char) {
+    if msg.is_null() { return; }
+    unsafe {
+        if let Ok(c_str) = CStr::from_ptr(msg).to_str() {
+            logger::insert_at(index, c_str.to_string());
+        }
+    }
+}
+
+/// Empties the scrollback and returns its lines, oldest first, joined with
+/// '\n'. The string must be released with `terminal_free_string`. Returns
+/// null if the terminal hasn't started.
+#[no_mangle]
+pub extern "C" fn terminal_drain_messages() -> *mut c_char {
+    let Some(entries) = logger::drain() else {
+        return std::ptr::null_mut();
+    };
+    let lines: Vec<String> = entries.iter().map(|e| e.to_string()).collect();
+    // Interior NULs can't cross the C boundary.
+    match CString::new(lines.join("\n").replace('\0', "")) {
+        Ok(c_string) => c_string.into_raw(),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// Frees a string returned by this library.
+#[no_mangle]
+pub extern "C" fn terminal_free_string(s: *mut c_char) {
+    if s.is_null() { return; }
+    unsafe { drop(CString::from_raw(s)); }
+}
+
+/// Positive `lines` scrolls up into history, negative toward the newest line.
+#[no_mangle]
+pub extern "C" fn terminal_scroll(lines: i32) {
+    logger::scroll_by(lines as isize);
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_scroll_to_top() {
+    logger::scroll_to_top();
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_scroll_to_bottom() {
+    logger::scroll_to_bottom();
+}
+
+/// Whether `terminal_start` prints the banner. Disable it before restarting
+/// the terminal for a reconnect so the logo isn't shown twice.
+#[no_mangle]
+pub extern "C" fn terminal_set_banner_enabled(enabled: bool) {
+    BANNER_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_show_banner() {
+    show_banner();
+}
+
+/// Mirrors every logged line, without ANSI codes, to stderr while it is
+/// redirected away from the terminal (e.g. `2>session.log`). Call after
+/// `terminal_start`. Returns 1 if the mirror is on, 0 if it's off, which
+/// includes when stderr is the terminal or the terminal isn't running.
+#[no_mangle]
+pub extern "C" fn terminal_set_stderr_mirror(enabled: c_int) -> c_int {
+    logger::set_stderr_mirror(enabled != 0) as c_int
+}
+
+/// Removes a timestamp matching `format` (e.g. "[%H:%M:%S]", see
+/// `text::timestamp_prefix`) from the start of incoming lines, for
+/// backends that stamp their own output. NULL turns it off. Call after
+/// `terminal_start`. Returns 0, or -1 if the terminal isn't running or
+/// `format` isn't UTF-8.
+#[no_mangle]
+pub extern "C" fn terminal_set_strip_timestamp(format: *const c_char) -> c_int {
+    let format = if format.is_null() {
+        None
+    } else {
+        match unsafe { CStr::from_ptr(format) }.to_str() {
+            Ok(format) => Some(format),
+            Err(_) => return -1,
+        }
+    };
+    if logger::set_strip_incoming_timestamp(format) { 0 } else { -1 }
+}
+
+/// Logs sample lines covering colors, wide characters, wrapping and
+/// scrolling, to check what the terminal renders (e.g. for a bug report).
+/// Returns 0, or -1 if the terminal isn't running.
+#[no_mangle]
+pub extern "C" fn terminal_run_diagnostics() -> c_int {
+    if logger::run_diagnostics() { 0 } else { -1 }
+}
+
+/// Dimmed hint shown in the empty input line, e.g. "type a command, Tab to
+/// complete". Can be set before or after `terminal_start`; an empty string
+/// clears it.
+#[no_mangle]
+pub extern "C" fn terminal_set_placeholder(msg: *const c_char) {
+    if msg.is_null() { return; }
+    unsafe {
+        if let Ok(c_str) = CStr::from_ptr(msg).to_str() {
+            if let Ok(mut placeholder) = locks::lock(&PLACEHOLDER, Rank::Leaf) {
+                *placeholder = c_str.to_string();
+            }
+            logger::set_placeholder(c_str);
+        }
+    }
+}
+
+/// Returns 0 on success, -1 if the file couldn't be opened or the terminal
+/// hasn't started yet.
+#[no_mangle]
+pub extern "C" fn terminal_set_log_file(path: *const c_char, max_size: u64) -> c_int {
+    if path.is_null() { return -1; }
+    unsafe {
+        match CStr::from_ptr(path).to_str() {
+            Ok(c_str) => match logger::set_log_file(Path::new(c_str), max_size) {
+                Ok(()) => 0,
+                Err(_) => -1,
+            },
+            Err(_) => -1,
+        }
+    }
+}
+
+/// Command history is loaded from `path` when the terminal starts and the
+/// full history written back on exit, creating the directory if needed.
+/// Call before `terminal_start`. Returns 0, or -1 for an invalid path.
+#[no_mangle]
+pub extern "C" fn terminal_set_history_file(path: *const c_char) -> c_int {
+    if path.is_null() { return -1; }
+    unsafe {
+        match CStr::from_ptr(path).to_str() {
+            Ok(c_str) => match locks::lock(&HISTORY_FILE, Rank::Leaf) {
+                Ok(mut history_file) => {
+                    *history_file = Some(PathBuf::from(c_str));
+                    0
+                }
+                Err(_) => -1,
+            },
+            Err(_) => -1,
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_close_log_file() {
+    logger::close_log_file();
+}
+
+/// Cursor position in the input line, counted in chars rather than bytes.
+#[no_mangle]
+pub extern "C" fn terminal_get_cursor() -> usize {
+    logger::cursor()
+}
+
+/// Moves the input cursor to `pos` chars, clamped to the input length.
+#[no_mangle]
+pub extern "C" fn terminal_set_cursor(pos: usize) {
+    logger::set_cursor(pos);
+}
+
+#[no_mangle]
+pub extern "C" fn terminal_set_verbose(enabled: bool) {
+    logger::set_verbose(enabled);
+}
+
+/// Prints `msg` to the normal screen buffer so it is still visible after
+/// the terminal exits. While the terminal is running, including after
+/// `terminal_close` until `terminal_start` returns, lines are queued and
+/// printed in order right after the alternate screen is left; once it has
+/// exited they are printed immediately.
+#[no_mangle]
+pub extern "C" fn terminal_print_final(msg: *const c_char) {
+    if msg.is_null() { return; }
+    unsafe {
+        if let Ok(c_str) = CStr::from_ptr(msg).to_str() {
+            let messages = locks::lock(&FINAL_MESSAGES, Rank::Leaf);
+            match messages {
+                Ok(mut messages) if TERMINAL_RUNNING.load(Ordering::Relaxed) => messages.push(c_str.to_string()),
+                // Printed with the lock still held, so it can't land among
+                // the queued lines while `terminal_start` prints them.
+                _ => println!("{}", c_str),
+            }
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn terminal_close() {
     SHUTDOWN_SIGNAL.store(true, Ordering::Relaxed);
@@ -70,23 +477,46 @@ pub extern "C" fn terminal_add_candidate(candidate: *const c_char) {
     if candidate.is_null() { return; }
     unsafe {
         if let Ok(c_str) = CStr::from_ptr(candidate).to_str() {
-            if let Ok(mut list) = COMPLETION_CANDIDATES.lock() {
-                list.push(c_str.to_string());
+            if let Ok(mut list) = locks::lock(&COMPLETION_CANDIDATES, Rank::Leaf) {
+                list.push(Completion::from(c_str));
             }
         }
     }
 }
 
+/// Like `terminal_add_candidate`, with a description shown dimmed beside
+/// the candidate when candidates are listed. `desc` may be null.
+#[no_mangle]
+pub extern "C" fn terminal_add_candidate_desc(candidate: *const c_char, desc: *const c_char) {
+    if candidate.is_null() { return; }
+    unsafe {
+        let Ok(value) = CStr::from_ptr(candidate).to_str() else { return; };
+        let description = if desc.is_null() {
+            ""
+        } else {
+            CStr::from_ptr(desc).to_str().unwrap_or("")
+        };
+        if let Ok(mut list) = locks::lock(&COMPLETION_CANDIDATES, Rank::Leaf) {
+            list.push(Completion::new(value, description));
+        }
+    }
+}
+
+/// Receives a NUL-terminated UTF-8 string, valid only during the call.
+///
+/// No callback registered here may unwind: catch exceptions (and, in Rust,
+/// panics) before returning. Unwinding out of an `extern "C"` function
+/// aborts the process or is undefined behavior, and can't be recovered
+/// from on this side.
 pub type NativeCallback = extern "C" fn(*const c_char);
 static mut RAW_INPUT_CB: Option<NativeCallback> = None;
 static mut RAW_TAB_CB: Option<NativeCallback> = None;
+static COMPLETE_CURSOR: AtomicUsize = AtomicUsize::new(0);
 
 fn invoke_native_callback(cb_opt: Option<NativeCallback>, data: &str) {
     if let Some(cb) = cb_opt {
         if let Ok(c_string) = CString::new(data) {
-            unsafe {
-                cb(c_string.as_ptr());
-            }
+            cb(c_string.as_ptr());
         }
     }
 }
@@ -102,24 +532,217 @@ pub extern "C" fn terminal_register_input_callback(callback: NativeCallback) {
     });
 }
 
+/// Cursor position, in chars, within the buffer passed to the current tab
+/// callback. Only meaningful while that callback runs; the callback ABI
+/// itself is unchanged, so existing callbacks keep working without it.
+#[no_mangle]
+pub extern "C" fn terminal_get_complete_cursor() -> usize {
+    COMPLETE_CURSOR.load(Ordering::Relaxed)
+}
+
 #[no_mangle]
 pub extern "C" fn terminal_register_tab_callback(callback: NativeCallback) {
     unsafe { RAW_TAB_CB = Some(callback); }
 
     JAVA_TAB_CALLBACK.get_or_init(|| {
-        Box::new(move |buffer| {
+        Box::new(move |buffer, cursor| {
+            COMPLETE_CURSOR.store(cursor, Ordering::Relaxed);
             unsafe { invoke_native_callback(RAW_TAB_CB, buffer); }
         })
     });
 }
 
+static mut RAW_TRANSFORM_CB: Option<NativeCallback> = None;
+
+/// Called with each submitted line before the input callback sees it. To
+/// rewrite the line, call `terminal_set_transformed_input` from inside the
+/// callback; otherwise it is dispatched unchanged.
+#[no_mangle]
+pub extern "C" fn terminal_register_transform_callback(callback: NativeCallback) {
+    unsafe { RAW_TRANSFORM_CB = Some(callback); }
+
+    JAVA_TRANSFORM_CALLBACK.get_or_init(|| {
+        Box::new(move |input| {
+            unsafe { invoke_native_callback(RAW_TRANSFORM_CB, input); }
+        })
+    });
+}
+
+/// Sets the line to dispatch in place of the one passed to the transform
+/// callback. `history` may be null to record the typed line in history.
+#[no_mangle]
+pub extern "C" fn terminal_set_transformed_input(line: *const c_char, history: *const c_char) {
+    if line.is_null() { return; }
+    unsafe {
+        let Ok(line) = CStr::from_ptr(line).to_str() else { return; };
+        let history = if history.is_null() {
+            None
+        } else {
+            CStr::from_ptr(history).to_str().ok().map(str::to_string)
+        };
+        if let Ok(mut result) = locks::lock(&TRANSFORMED_INPUT, Rank::Leaf) {
+            *result = Some(TransformedInput { line: line.to_string(), history });
+        }
+    }
+}
+
+/// Asks a follow-up question: shows `prompt` instead of the usual prompt
+/// for the next line, which goes to `callback` rather than the input
+/// callback, then the usual prompt returns. `callback` gets null instead if
+/// the question is cancelled, by Escape, another `terminal_prompt_once` or
+/// the terminal exiting. It runs on the UI thread. The answer is added to
+/// history only if `remember` is non-zero.
+///
+/// Returns 0, or -1 if the terminal isn't running.
+#[no_mangle]
+pub extern "C" fn terminal_prompt_once(prompt: *const c_char, callback: NativeCallback, remember: c_int) -> c_int {
+    if prompt.is_null() || !TERMINAL_RUNNING.load(Ordering::Relaxed) { return -1; }
+    let Ok(prompt) = (unsafe { CStr::from_ptr(prompt) }).to_str() else { return -1; };
+    let asked = logger::prompt_once(prompt, remember != 0, move |answer| match answer {
+        Some(line) => invoke_native_callback(Some(callback), &line),
+        None => callback(std::ptr::null()),
+    });
+    if asked { 0 } else { -1 }
+}
+
+/// Receives `(key_code, modifiers)` for keys the terminal doesn't handle itself.
+/// `key_code` is the Unicode scalar for printable keys, or one of the `KEY_*`
+/// constants in `core::keys` (F1..F12 are `KEY_F0 + n`). `modifiers` is a
+/// bitmask: 1 = Shift, 2 = Control, 4 = Alt, 8 = Super.
+/// Return non-zero if the key was handled, zero to leave it unhandled.
+pub type NativeKeyCallback = extern "C" fn(u32, u32) -> c_int;
+static mut RAW_KEY_CB: Option<NativeKeyCallback> = None;
+
+#[no_mangle]
+pub extern "C" fn terminal_register_key_callback(callback: NativeKeyCallback) {
+    unsafe { RAW_KEY_CB = Some(callback); }
+
+    JAVA_KEY_CALLBACK.get_or_init(|| {
+        Box::new(move |code, modifiers| {
+            match unsafe { RAW_KEY_CB } {
+                Some(cb) => cb(code, modifiers) != 0,
+                None => false,
+            }
+        })
+    });
+}
+
+/// Observes every key press, before the terminal handles it, with the same
+/// `(key_code, modifiers)` encoding as the key callback. Purely
+/// informational: unlike `terminal_register_key_callback` it can't consume
+/// the key, and it also sees keys the terminal handles itself.
+pub type NativeKeypressCallback = extern "C" fn(u32, u32);
+static mut RAW_KEYPRESS_CB: Option<NativeKeypressCallback> = None;
+
+#[no_mangle]
+pub extern "C" fn terminal_register_keypress_callback(callback: NativeKeypressCallback) {
+    unsafe { RAW_KEYPRESS_CB = Some(callback); }
+
+    JAVA_KEYPRESS_CALLBACK.get_or_init(|| {
+        Box::new(move |code, modifiers| {
+            if let Some(cb) = unsafe { RAW_KEYPRESS_CB } {
+                cb(code, modifiers);
+            }
+        })
+    });
+}
+
+/// Returns the library to its freshly loaded state so it can be unloaded
+/// or set up again from scratch. Unregisters every callback, clears
+/// `terminal_close`'s shutdown request, drops the scrollback, queued
+/// messages, completion candidates, placeholder, pending transform result
+/// and `terminal_print_final` lines, forgets the history file and heartbeat
+/// interval, and turns the banner back on and verbose mode off.
+///
+/// Registering a callback afterwards works as on first load. What stays is
+/// internal: each callback slot keeps a small forwarding closure that does
+/// nothing until a callback is registered again.
+///
+/// Returns 0, or -1 without changing anything if the terminal is running;
+/// call `terminal_close` and wait for `terminal_start` to return first.
+#[no_mangle]
+pub extern "C" fn terminal_shutdown_native() -> c_int {
+    let Ok(mut final_messages) = locks::lock(&FINAL_MESSAGES, Rank::Leaf) else {
+        return -1;
+    };
+    if TERMINAL_RUNNING.load(Ordering::Relaxed) {
+        return -1;
+    }
+    final_messages.clear();
+    drop(final_messages);
+
+    unsafe {
+        RAW_INPUT_CB = None;
+        RAW_TAB_CB = None;
+        RAW_TRANSFORM_CB = None;
+        RAW_KEY_CB = None;
+        RAW_KEYPRESS_CB = None;
+        RAW_HEARTBEAT_CB = None;
+    }
+    HEARTBEAT_INTERVAL_MS.store(0, Ordering::Relaxed);
+    SHUTDOWN_SIGNAL.store(false, Ordering::Relaxed);
+    COMPLETE_CURSOR.store(0, Ordering::Relaxed);
+    BANNER_ENABLED.store(true, Ordering::Relaxed);
+
+    logger::reset();
+    if let Ok(mut candidates) = locks::lock(&COMPLETION_CANDIDATES, Rank::Leaf) {
+        candidates.clear();
+    }
+    if let Ok(mut result) = locks::lock(&TRANSFORMED_INPUT, Rank::Leaf) {
+        *result = None;
+    }
+    if let Ok(mut placeholder) = locks::lock(&PLACEHOLDER, Rank::Leaf) {
+        placeholder.clear();
+    }
+    if let Ok(mut history_file) = locks::lock(&HISTORY_FILE, Rank::Leaf) {
+        *history_file = None;
+    }
+    0
+}
+
+/// Called every `terminal_set_heartbeat_interval` milliseconds while the
+/// terminal runs, regardless of user activity, e.g. to send keepalives
+/// without a timer thread of your own. It runs on the UI thread between
+/// frames, so it should return quickly. Stops once `terminal_close` is
+/// called.
+pub type NativeHeartbeatCallback = extern "C" fn();
+static mut RAW_HEARTBEAT_CB: Option<NativeHeartbeatCallback> = None;
+
+#[no_mangle]
+pub extern "C" fn terminal_register_heartbeat_callback(callback: NativeHeartbeatCallback) {
+    unsafe { RAW_HEARTBEAT_CB = Some(callback); }
+
+    JAVA_HEARTBEAT_CALLBACK.get_or_init(|| {
+        Box::new(|| {
+            if let Some(cb) = unsafe { RAW_HEARTBEAT_CB } {
+                cb();
+            }
+        })
+    });
+}
+
+/// Interval for the heartbeat callback; 0 (the default) turns it off.
+/// Takes effect at the next `terminal_start`.
+#[no_mangle]
+pub extern "C" fn terminal_set_heartbeat_interval(ms: u64) {
+    HEARTBEAT_INTERVAL_MS.store(ms, Ordering::Relaxed);
+}
+
+// terminal_start runs before and after the TUI owns the screen, so its
+// traces go to stderr rather than the pane.
+fn debug_trace(message: &str) {
+    if logger::is_verbose() {
+        eprintln!("[RUST DEBUG] {}", message);
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn terminal_start() {
-    eprintln!("[RUST DEBUG] terminal_start() called");
+    debug_trace("terminal_start() called");
 
     let runtime = match tokio::runtime::Runtime::new() {
         Ok(rt) => {
-            eprintln!("[RUST DEBUG] Tokio runtime created successfully");
+            debug_trace("Tokio runtime created successfully");
             rt
         },
         Err(e) => {
@@ -128,17 +751,17 @@ pub extern "C" fn terminal_start() {
         }
     };
 
-    eprintln!("[RUST DEBUG] Starting terminal in async block...");
+    debug_trace("Starting terminal in async block...");
     runtime.block_on(async {
-        eprintln!("[RUST DEBUG] Inside async block, creating Terminal");
+        debug_trace("Inside async block, creating Terminal");
         let mut terminal = Terminal::new();
-        eprintln!("[RUST DEBUG] Terminal created, calling run()");
+        debug_trace("Terminal created, calling run()");
 
         match terminal.run().await {
-            Ok(_) => eprintln!("[RUST DEBUG] Terminal run() completed successfully"),
+            Ok(_) => debug_trace("Terminal run() completed successfully"),
             Err(e) => eprintln!("[RUST ERROR] Terminal error: {}", e),
         }
     });
 
-    eprintln!("[RUST DEBUG] terminal_start() ending");
+    debug_trace("terminal_start() ending");
 }
\ No newline at end of file
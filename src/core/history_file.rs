@@ -0,0 +1,237 @@
+use crate::core::ui::{CommandOutcome, HistoryEntry};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// Most commands kept in a history file, and most annotated entries; older
+/// ones are dropped on save.
+pub const HISTORY_FILE_CAPACITY: usize = 1000;
+
+// First line of files whose commands are escaped, so one spanning several
+// lines stays one command. Files without it hold one command per line and
+// no annotated entries.
+const HEADER: &str = "#riege-history 2";
+
+/// What a history file holds, oldest first.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct SavedHistory {
+    pub commands: Vec<String>,
+    /// The record kept by `TerminalUI::set_annotated_history`.
+    pub annotated: Vec<HistoryEntry>,
+}
+
+/// Reads a saved history. A missing file is an empty history; annotated
+/// entries that don't parse are skipped.
+pub fn load(path: &Path) -> io::Result<SavedHistory> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(SavedHistory::default()),
+        Err(e) => return Err(e),
+    };
+    let mut lines = contents.lines().peekable();
+    let mut saved = SavedHistory::default();
+    if lines.next_if_eq(&HEADER).is_none() {
+        saved.commands = lines.filter(|l| !l.trim().is_empty()).map(str::to_string).collect();
+        return Ok(saved);
+    }
+    for line in lines.filter(|l| !l.trim().is_empty()) {
+        // Escaped commands never start with a tab, so one marks an entry.
+        match line.strip_prefix('\t') {
+            Some(fields) => saved.annotated.extend(parse_entry(fields)),
+            None => saved.commands.push(unescape(line)),
+        }
+    }
+    Ok(saved)
+}
+
+/// Replaces the file with the last `HISTORY_FILE_CAPACITY` commands, one
+/// per line with newlines escaped, creating its directory if needed. The
+/// write goes through a temporary file so a crash mid-save leaves the old
+/// history intact. Gives up with `TimedOut` after `timeout`, leaving the
+/// write to finish in the background, so a slow disk can't hold up exit.
+pub fn save(path: &Path, history: &[String], annotated: &[HistoryEntry], timeout: Duration) -> io::Result<()> {
+    let path = path.to_path_buf();
+    let history = history[history.len().saturating_sub(HISTORY_FILE_CAPACITY)..].to_vec();
+    let annotated = annotated[annotated.len().saturating_sub(HISTORY_FILE_CAPACITY)..].to_vec();
+    let (sender, receiver) = mpsc::channel();
+
+    thread::Builder::new()
+        .name("riege-history-file".to_string())
+        .spawn(move || {
+            let _ = sender.send(write(&path, &history, &annotated));
+        })?;
+
+    receiver
+        .recv_timeout(timeout)
+        .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "saving history timed out")))
+}
+
+fn write(path: &Path, history: &[String], annotated: &[HistoryEntry]) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    let temp = PathBuf::from(format!("{}.tmp", path.display()));
+    let mut file = io::BufWriter::new(fs::File::create(&temp)?);
+    writeln!(file, "{}", HEADER)?;
+    for command in history {
+        writeln!(file, "{}", escape(command))?;
+    }
+    for entry in annotated {
+        writeln!(file, "\t{}", format_entry(entry))?;
+    }
+    file.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    fs::rename(temp, path)
+}
+
+// Tab-separated: milliseconds since the epoch, "ok" or "failed", the
+// command, and for failures the error.
+fn format_entry(entry: &HistoryEntry) -> String {
+    let millis = entry.timestamp.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default().as_millis();
+    match &entry.outcome {
+        CommandOutcome::Succeeded => format!("{}\tok\t{}", millis, escape(&entry.command)),
+        CommandOutcome::Failed(error) => format!("{}\tfailed\t{}\t{}", millis, escape(&entry.command), escape(error)),
+    }
+}
+
+fn parse_entry(fields: &str) -> Option<HistoryEntry> {
+    let mut fields = fields.split('\t');
+    let millis: u64 = fields.next()?.parse().ok()?;
+    let outcome = fields.next()?;
+    let command = unescape(fields.next()?);
+    let outcome = match (outcome, fields.next()) {
+        ("ok", None) => CommandOutcome::Succeeded,
+        ("failed", Some(error)) => CommandOutcome::Failed(unescape(error)),
+        _ => return None,
+    };
+    let timestamp = SystemTime::UNIX_EPOCH + Duration::from_millis(millis);
+    Some(HistoryEntry { command, timestamp, outcome })
+}
+
+// Backslash escapes for the characters that would split a record.
+fn escape(command: &str) -> String {
+    let mut escaped = String::with_capacity(command.len());
+    for c in command.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn unescape(line: &str) -> String {
+    let mut command = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            command.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => command.push('\n'),
+            Some('r') => command.push('\r'),
+            Some('t') => command.push('\t'),
+            Some(other) => command.push(other),
+            None => command.push('\\'),
+        }
+    }
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("riege-history-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn save_creates_the_missing_directory() {
+        let dir = scratch_dir("missing");
+        let path = dir.join("nested").join("history");
+        let history = vec!["status".to_string(), "quit".to_string()];
+
+        save(&path, &history, &[], Duration::from_secs(5)).unwrap();
+        assert_eq!(load(&path).unwrap().commands, history);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn multi_line_commands_survive_a_round_trip() {
+        let dir = scratch_dir("round-trip");
+        let path = dir.join("history");
+        let history = vec![
+            "for x in a b\ndo echo $x\ndone".to_string(),
+            r"C:\new\table".to_string(),
+            "a\tb".to_string(),
+        ];
+
+        save(&path, &history, &[], Duration::from_secs(5)).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+        assert_eq!(load(&path).unwrap().commands, history);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn files_without_a_header_are_read_verbatim() {
+        let dir = scratch_dir("verbatim");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history");
+        fs::write(&path, "status\ncd C:\\new\n\n").unwrap();
+
+        assert_eq!(load(&path).unwrap().commands, ["status", r"cd C:\new"]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn annotated_entries_are_kept_beside_the_commands() {
+        let dir = scratch_dir("annotated");
+        let path = dir.join("history");
+        let at = |millis| SystemTime::UNIX_EPOCH + Duration::from_millis(millis);
+        let history = vec!["deploy\tprod".to_string(), "status".to_string()];
+        let annotated: Vec<HistoryEntry> = (0..HISTORY_FILE_CAPACITY as u64 + 1)
+            .map(|i| HistoryEntry {
+                command: format!("deploy\tprod {}", i),
+                timestamp: at(1_700_000_000_123 + i),
+                outcome: match i % 2 {
+                    0 => CommandOutcome::Succeeded,
+                    _ => CommandOutcome::Failed("no\nroute".to_string()),
+                },
+            })
+            .collect();
+
+        save(&path, &history, &annotated, Duration::from_secs(5)).unwrap();
+        let saved = load(&path).unwrap();
+        assert_eq!(saved.commands, history);
+        assert_eq!(saved.annotated, annotated[1..]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    // A FIFO in place of the temporary file blocks the write until someone
+    // reads it, which nobody does.
+    #[cfg(unix)]
+    #[test]
+    fn save_gives_up_after_the_timeout() {
+        use std::ffi::CString;
+
+        let dir = scratch_dir("timeout");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("history");
+        let fifo = CString::new(format!("{}.tmp", path.display())).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o600) }, 0);
+
+        let error = save(&path, &["status".to_string()], &[], Duration::from_millis(50)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        assert!(!path.exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+//! Logo image for the startup banner, on terminals that can show images
+//! inline (kitty's graphics protocol, iTerm2's inline images).
+//!
+//! Off unless built with the `image-banner` feature and
+//! `RIEGE_XTERM_BANNER_IMAGE` names a PNG file. Detection only trusts
+//! environment variables the terminals set themselves, and gives up inside
+//! tmux or screen, which don't pass the escapes through. Anywhere else the
+//! text banner is used.
+
+use std::fs;
+use std::path::Path;
+use super::text::base64;
+
+pub const BANNER_IMAGE_ENV: &str = "RIEGE_XTERM_BANNER_IMAGE";
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+// Largest base64 payload kitty accepts per escape.
+const KITTY_CHUNK: usize = 4096;
+// Fixed so a later placement replaces the earlier one.
+const KITTY_IMAGE_ID: u32 = 7413;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageProtocol {
+    Kitty,
+    Iterm2,
+}
+
+impl ImageProtocol {
+    /// The protocol the terminal we're running in understands, if any.
+    pub fn detect() -> Option<Self> {
+        let var = |name: &str| std::env::var(name).unwrap_or_default();
+        if !var("TMUX").is_empty() || !var("STY").is_empty() {
+            return None;
+        }
+        if var("TERM") == "xterm-kitty" || !var("KITTY_WINDOW_ID").is_empty() {
+            return Some(ImageProtocol::Kitty);
+        }
+        match var("TERM_PROGRAM").as_str() {
+            "iTerm.app" | "WezTerm" => Some(ImageProtocol::Iterm2),
+            _ => None,
+        }
+    }
+}
+
+pub struct BannerImage {
+    protocol: ImageProtocol,
+    // Base64 of the PNG.
+    encoded: String,
+}
+
+impl BannerImage {
+    /// The image named by `RIEGE_XTERM_BANNER_IMAGE`, if the terminal can
+    /// show it and the file is a readable PNG.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var_os(BANNER_IMAGE_ENV)?;
+        Self::load(ImageProtocol::detect()?, Path::new(&path))
+    }
+
+    pub fn load(protocol: ImageProtocol, path: &Path) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        if !data.starts_with(PNG_SIGNATURE) {
+            return None;
+        }
+        Some(Self { protocol, encoded: base64(&data) })
+    }
+
+    /// Draws the image scaled into `columns` x `rows` cells from the
+    /// cursor position, leaving the cursor where it was.
+    pub fn escape(&self, columns: u16, rows: u16) -> String {
+        match self.protocol {
+            ImageProtocol::Kitty => {
+                let chunks: Vec<&str> = self
+                    .encoded
+                    .as_bytes()
+                    .chunks(KITTY_CHUNK)
+                    .map(|chunk| std::str::from_utf8(chunk).unwrap())
+                    .collect();
+                let mut escape = String::new();
+                for (i, chunk) in chunks.iter().enumerate() {
+                    let more = (i + 1 < chunks.len()) as u8;
+                    if i == 0 {
+                        escape.push_str(&format!(
+                            "\x1b_Ga=T,f=100,q=2,C=1,i={},c={},r={},m={};{}\x1b\\",
+                            KITTY_IMAGE_ID, columns, rows, more, chunk
+                        ));
+                    } else {
+                        escape.push_str(&format!("\x1b_Gm={};{}\x1b\\", more, chunk));
+                    }
+                }
+                escape
+            }
+            ImageProtocol::Iterm2 => format!(
+                "\x1b7\x1b]1337;File=inline=1;width={};height={};preserveAspectRatio=1:{}\x07\x1b8",
+                columns, rows, self.encoded
+            ),
+        }
+    }
+
+    /// Removes the image again. iTerm2 images are erased by redrawing the
+    /// cells under them, so there is nothing to send.
+    pub fn clear_escape(&self) -> Option<String> {
+        match self.protocol {
+            ImageProtocol::Kitty => Some(format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", KITTY_IMAGE_ID)),
+            ImageProtocol::Iterm2 => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn kitty_payload_is_split_into_chunks() {
+        let image = BannerImage { protocol: ImageProtocol::Kitty, encoded: "A".repeat(KITTY_CHUNK + 4) };
+        let escape = image.escape(40, 6);
+        assert_eq!(escape.matches("\x1b_G").count(), 2);
+        assert!(escape.starts_with("\x1b_Ga=T,f=100,q=2,C=1,i=7413,c=40,r=6,m=1;"));
+        assert!(escape.ends_with("\x1b_Gm=0;AAAA\x1b\\"));
+    }
+}
@@ -0,0 +1,201 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+// Key codes passed to native callbacks. Printable keys are sent as their
+// Unicode scalar value; everything else is placed above the Unicode range.
+pub const KEY_BACKSPACE: u32 = 0x11_0000;
+pub const KEY_ENTER: u32 = 0x11_0001;
+pub const KEY_LEFT: u32 = 0x11_0002;
+pub const KEY_RIGHT: u32 = 0x11_0003;
+pub const KEY_UP: u32 = 0x11_0004;
+pub const KEY_DOWN: u32 = 0x11_0005;
+pub const KEY_HOME: u32 = 0x11_0006;
+pub const KEY_END: u32 = 0x11_0007;
+pub const KEY_PAGE_UP: u32 = 0x11_0008;
+pub const KEY_PAGE_DOWN: u32 = 0x11_0009;
+pub const KEY_TAB: u32 = 0x11_000A;
+pub const KEY_BACK_TAB: u32 = 0x11_000B;
+pub const KEY_DELETE: u32 = 0x11_000C;
+pub const KEY_INSERT: u32 = 0x11_000D;
+pub const KEY_ESC: u32 = 0x11_000E;
+// F1..F12 are KEY_F0 + n.
+pub const KEY_F0: u32 = 0x11_0100;
+pub const KEY_UNKNOWN: u32 = 0x11_FFFF;
+
+// Modifier bits, identical to crossterm's KeyModifiers.
+pub const MOD_SHIFT: u32 = 0b0000_0001;
+pub const MOD_CONTROL: u32 = 0b0000_0010;
+pub const MOD_ALT: u32 = 0b0000_0100;
+pub const MOD_SUPER: u32 = 0b0000_1000;
+
+pub fn encode_key_code(code: KeyCode) -> u32 {
+    match code {
+        KeyCode::Char(c) => c as u32,
+        KeyCode::Backspace => KEY_BACKSPACE,
+        KeyCode::Enter => KEY_ENTER,
+        KeyCode::Left => KEY_LEFT,
+        KeyCode::Right => KEY_RIGHT,
+        KeyCode::Up => KEY_UP,
+        KeyCode::Down => KEY_DOWN,
+        KeyCode::Home => KEY_HOME,
+        KeyCode::End => KEY_END,
+        KeyCode::PageUp => KEY_PAGE_UP,
+        KeyCode::PageDown => KEY_PAGE_DOWN,
+        KeyCode::Tab => KEY_TAB,
+        KeyCode::BackTab => KEY_BACK_TAB,
+        KeyCode::Delete => KEY_DELETE,
+        KeyCode::Insert => KEY_INSERT,
+        KeyCode::Esc => KEY_ESC,
+        KeyCode::F(n) => KEY_F0 + n as u32,
+        _ => KEY_UNKNOWN,
+    }
+}
+
+pub fn encode_modifiers(modifiers: KeyModifiers) -> u32 {
+    modifiers.bits() as u32
+}
+
+pub fn encode_key(key: &KeyEvent) -> (u32, u32) {
+    (encode_key_code(key.code), encode_modifiers(key.modifiers))
+}
+
+/// Human-readable name of a key combination, e.g. "Ctrl+T", for the help
+/// overlay.
+pub fn key_label(code: KeyCode, modifiers: KeyModifiers) -> String {
+    let mut label = String::new();
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        label.push_str("Ctrl+");
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        label.push_str("Alt+");
+    }
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        label.push_str("Shift+");
+    }
+    match code {
+        KeyCode::Char(c) => label.extend(c.to_uppercase()),
+        KeyCode::F(n) => label.push_str(&format!("F{}", n)),
+        other => label.push_str(&format!("{:?}", other)),
+    }
+    label
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WordMotion {
+    Left,
+    Right,
+}
+
+// Terminals disagree on what word movement sends, so several shapes map to
+// the same motion:
+//
+//   terminal              Ctrl+Left/Right          Alt+Left/Right
+//   xterm, VTE, kitty     Left/Right + CONTROL     Left/Right + ALT
+//   Windows Terminal      Left/Right + CONTROL     Left/Right + ALT
+//   iTerm2 (default)      Left/Right + CONTROL     ESC b / ESC f
+//   Terminal.app          (system shortcut)        ESC b / ESC f with
+//                                                  "Use Option as Meta"
+//
+// ESC b / ESC f arrive from crossterm as Char('b') / Char('f') + ALT, the
+// readline word bindings, so they are treated as word movement too. Extra
+// SHIFT (Ctrl+Shift+Left) is ignored. Terminals that send the plain arrow
+// sequence for Ctrl+Left (rxvt's ESC O d, tmux without xterm-keys) can't
+// be told apart from a bare arrow; Alt+b / Alt+f is the fallback there.
+pub fn word_motion(key: &KeyEvent) -> Option<WordMotion> {
+    let word_modifier = key.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT);
+    match key.code {
+        KeyCode::Left if word_modifier => Some(WordMotion::Left),
+        KeyCode::Right if word_modifier => Some(WordMotion::Right),
+        KeyCode::Char('b') if key.modifiers.contains(KeyModifiers::ALT) => Some(WordMotion::Left),
+        KeyCode::Char('f') if key.modifiers.contains(KeyModifiers::ALT) => Some(WordMotion::Right),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    ShowHelp,
+    ClearMessages,
+    ScrollToTop,
+    ScrollToBottom,
+    ToggleErrorPanel,
+    SearchHistory,
+    ToggleFolds,
+    SelectFold,
+    CycleLevelFilter,
+    Exit,
+}
+
+impl Action {
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::ShowHelp => "Show this help",
+            Action::ClearMessages => "Clear messages",
+            Action::ScrollToTop => "Scroll to oldest message",
+            Action::ScrollToBottom => "Scroll to newest message",
+            Action::ToggleErrorPanel => "Toggle error summary panel",
+            Action::SearchHistory => "Search history",
+            Action::ToggleFolds => "Expand/collapse folded output",
+            Action::SelectFold => "Pick a fold to expand or collapse",
+            Action::CycleLevelFilter => "Hide debug, info or warning lines",
+            Action::Exit => "Quit",
+        }
+    }
+}
+
+/// Readline-style line editing, bound to Ctrl+W, Ctrl+K, Ctrl+U, Ctrl+Y
+/// and Ctrl+R by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditAction {
+    CutWordBefore,
+    CutToLineEnd,
+    CutToLineStart,
+    Paste,
+    ReverseSearch,
+}
+
+impl EditAction {
+    pub fn description(&self) -> &'static str {
+        match self {
+            EditAction::CutWordBefore => "Cut the word before the cursor",
+            EditAction::CutToLineEnd => "Cut to the end of the line",
+            EditAction::CutToLineStart => "Cut to the start of the line",
+            EditAction::Paste => "Paste the last cut text",
+            EditAction::ReverseSearch => "Search history as you type (again for older)",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn word_motion_from_modified_arrows() {
+        assert_eq!(word_motion(&key(KeyCode::Left, KeyModifiers::CONTROL)), Some(WordMotion::Left));
+        assert_eq!(word_motion(&key(KeyCode::Right, KeyModifiers::CONTROL)), Some(WordMotion::Right));
+        assert_eq!(word_motion(&key(KeyCode::Left, KeyModifiers::ALT)), Some(WordMotion::Left));
+        assert_eq!(word_motion(&key(KeyCode::Right, KeyModifiers::ALT)), Some(WordMotion::Right));
+        assert_eq!(
+            word_motion(&key(KeyCode::Left, KeyModifiers::CONTROL | KeyModifiers::SHIFT)),
+            Some(WordMotion::Left)
+        );
+    }
+
+    #[test]
+    fn word_motion_from_meta_letters() {
+        assert_eq!(word_motion(&key(KeyCode::Char('b'), KeyModifiers::ALT)), Some(WordMotion::Left));
+        assert_eq!(word_motion(&key(KeyCode::Char('f'), KeyModifiers::ALT)), Some(WordMotion::Right));
+    }
+
+    #[test]
+    fn plain_keys_are_not_word_motion() {
+        assert_eq!(word_motion(&key(KeyCode::Left, KeyModifiers::NONE)), None);
+        assert_eq!(word_motion(&key(KeyCode::Right, KeyModifiers::SHIFT)), None);
+        assert_eq!(word_motion(&key(KeyCode::Char('b'), KeyModifiers::NONE)), None);
+        assert_eq!(word_motion(&key(KeyCode::Char('f'), KeyModifiers::CONTROL)), None);
+    }
+}
@@ -0,0 +1,133 @@
+//! Lock ordering.
+//!
+//! Mutexes reachable from both FFI threads and the UI thread are only ever
+//! taken in increasing `Rank` order, which rules out lock-order deadlocks:
+//!
+//!   GlobalLogger → Pending → Messages → Trim → LogFile → (one leaf)
+//!
+//! Leaf locks (completion candidates, transform result, placeholder) may be
+//! taken while holding any of the others, but nothing else may be taken
+//! while holding a leaf. Callbacks into the embedder are never run with a
+//! lock held, since they may call back into the FFI.
+//!
+//! `lock` checks the order in debug builds and is a plain `Mutex::lock` in
+//! release builds.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::{LockResult, Mutex, MutexGuard, PoisonError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Rank {
+    GlobalLogger,
+    Pending,
+    Messages,
+    Trim,
+    LogFile,
+    Leaf,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    static HELD: std::cell::RefCell<Vec<Rank>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+struct Held {
+    #[cfg(debug_assertions)]
+    rank: Rank,
+}
+
+impl Held {
+    fn enter(rank: Rank) -> Held {
+        #[cfg(debug_assertions)]
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(&last) = held.iter().max() {
+                debug_assert!(
+                    last < rank,
+                    "lock order violated: taking {:?} while holding {:?}",
+                    rank,
+                    last
+                );
+            }
+            held.push(rank);
+        });
+        let _ = rank;
+        Held {
+            #[cfg(debug_assertions)]
+            rank,
+        }
+    }
+}
+
+impl Drop for Held {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        HELD.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(i) = held.iter().rposition(|&r| r == self.rank) {
+                held.remove(i);
+            }
+        });
+    }
+}
+
+/// A `MutexGuard` that also records its rank until dropped.
+pub(crate) struct Ranked<'a, T> {
+    // Declared first so the mutex is released before the rank is cleared.
+    guard: MutexGuard<'a, T>,
+    _held: Held,
+}
+
+impl<T> Deref for Ranked<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for Ranked<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
+}
+
+/// Locks `mutex`, which has the given rank, checking the lock order first.
+pub(crate) fn lock<T>(mutex: &Mutex<T>, rank: Rank) -> LockResult<Ranked<'_, T>> {
+    let held = Held::enter(rank);
+    match mutex.lock() {
+        Ok(guard) => Ok(Ranked { guard, _held: held }),
+        Err(poisoned) => Err(PoisonError::new(Ranked { guard: poisoned.into_inner(), _held: held })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn increasing_order_is_allowed() {
+        let a = Mutex::new(1);
+        let b = Mutex::new(2);
+        let _a = lock(&a, Rank::Messages).unwrap();
+        let _b = lock(&b, Rank::Trim).unwrap();
+    }
+
+    #[test]
+    fn rank_is_released_with_the_guard() {
+        let a = Mutex::new(1);
+        let b = Mutex::new(2);
+        drop(lock(&b, Rank::Trim).unwrap());
+        let _a = lock(&a, Rank::Messages).unwrap();
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "lock order violated")]
+    fn decreasing_order_panics() {
+        let a = Mutex::new(1);
+        let b = Mutex::new(2);
+        let _b = lock(&b, Rank::Trim).unwrap();
+        let _a = lock(&a, Rank::Messages).unwrap();
+    }
+}
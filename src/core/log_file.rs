@@ -0,0 +1,105 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Appends logged lines to a file from a background thread, rotating it
+/// once it grows past `max_size` bytes. The old file is renamed to
+/// `<path>.<unix seconds>` and a fresh one is started.
+pub struct LogFile {
+    sender: Sender<String>,
+}
+
+impl LogFile {
+    pub fn open(path: &Path, max_size: u64) -> io::Result<LogFile> {
+        let path = path.to_path_buf();
+        let file = open_append(&path)?;
+        let size = file.metadata()?.len();
+        let (sender, receiver) = mpsc::channel();
+
+        thread::Builder::new()
+            .name("riege-log-file".to_string())
+            .spawn(move || write_loop(path, max_size, file, size, receiver))?;
+
+        Ok(LogFile { sender })
+    }
+
+    pub fn write(&self, line: &str) {
+        // Fails only once the writer has given up after an error.
+        let _ = self.sender.send(line.to_string());
+    }
+}
+
+/// Writes lines to stderr from a background thread, so a slow reader at the
+/// other end of the redirect doesn't hold up logging.
+pub struct StderrMirror {
+    sender: Sender<String>,
+}
+
+impl StderrMirror {
+    pub fn start() -> io::Result<StderrMirror> {
+        let (sender, receiver) = mpsc::channel::<String>();
+        thread::Builder::new().name("riege-stderr".to_string()).spawn(move || {
+            // Nothing is left to report to once stderr itself fails.
+            for line in receiver {
+                if writeln!(io::stderr().lock(), "{}", line).is_err() {
+                    return;
+                }
+            }
+        })?;
+        Ok(StderrMirror { sender })
+    }
+
+    pub fn write(&self, line: &str) {
+        let _ = self.sender.send(line.to_string());
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotate(path: &Path) -> io::Result<File> {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let mut rotated = PathBuf::from(format!("{}.{}", path.display(), secs));
+    // Two rotations within the same second must not overwrite each other.
+    let mut n = 1;
+    while rotated.exists() {
+        rotated = PathBuf::from(format!("{}.{}-{}", path.display(), secs, n));
+        n += 1;
+    }
+    fs::rename(path, rotated)?;
+    open_append(path)
+}
+
+// Runs until every sender is dropped, or stops at the first I/O error after
+// reporting it once in the pane.
+fn write_loop(path: PathBuf, max_size: u64, file: File, mut size: u64, receiver: Receiver<String>) {
+    let mut writer = BufWriter::new(file);
+
+    for line in receiver {
+        let result = (|| -> io::Result<()> {
+            if size > 0 && size + line.len() as u64 + 1 > max_size {
+                writer.flush()?;
+                writer = BufWriter::new(rotate(&path)?);
+                size = 0;
+            }
+            writeln!(writer, "{}", line)?;
+            writer.flush()?;
+            size += line.len() as u64 + 1;
+            Ok(())
+        })();
+
+        if let Err(e) = result {
+            crate::core::logger::warning(&format!(
+                "Log file {} disabled: {}", path.display(), e
+            ));
+            return;
+        }
+    }
+}
@@ -1,50 +1,244 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
 use std::sync::{Mutex, OnceLock};
-use crate::core::ui::MessageLogger;
+use std::sync::atomic::{AtomicBool, Ordering};
+use crate::core::locks::{self, Rank};
+use crate::core::ui::{Level, LogEntry, LogStatus, MessageLogger};
+use ratatui::layout::Alignment;
+use ratatui::style::{Color, Style};
 
 pub static GLOBAL_LOGGER: OnceLock<Mutex<Option<MessageLogger>>> = OnceLock::new();
+static VERBOSE: AtomicBool = AtomicBool::new(false);
 
+// Messages logged before `set_logger`, oldest first. Past the cap the
+// oldest are discarded, so a logger that never arrives costs bounded memory.
+type PendingLog = Box<dyn FnOnce(&MessageLogger) -> LogStatus + Send>;
+static PENDING: Mutex<VecDeque<PendingLog>> = Mutex::new(VecDeque::new());
+const PENDING_CAPACITY: usize = 256;
+
+pub fn set_verbose(enabled: bool) {
+    VERBOSE.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_verbose() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// Installs `logger` and flushes into it, in order, anything logged while
+/// no logger was set (up to the last `PENDING_CAPACITY` messages).
 pub fn set_logger(logger: MessageLogger) {
     let lock = GLOBAL_LOGGER.get_or_init(|| Mutex::new(None));
-    let mut global = lock.lock().unwrap();
+    let mut global = locks::lock(lock, Rank::GlobalLogger).unwrap();
+    let pending = std::mem::take(&mut *locks::lock(&PENDING, Rank::Pending).unwrap());
+    for log in pending {
+        log(&logger);
+    }
     *global = Some(logger);
 }
 
-fn with_logger<F>(f: F)
-where F: FnOnce(&MessageLogger)
+/// Drops the installed logger and anything queued for one, and turns
+/// verbose mode off, as if nothing had been logged yet.
+pub fn reset() {
+    if let Some(lock) = GLOBAL_LOGGER.get() {
+        let mut global = locks::lock(lock, Rank::GlobalLogger).unwrap_or_else(|e| e.into_inner());
+        locks::lock(&PENDING, Rank::Pending).unwrap_or_else(|e| e.into_inner()).clear();
+        *global = None;
+    } else {
+        locks::lock(&PENDING, Rank::Pending).unwrap_or_else(|e| e.into_inner()).clear();
+    }
+    set_verbose(false);
+}
+
+fn with_logger<F, R>(f: F) -> Option<R>
+where F: FnOnce(&MessageLogger) -> R
 {
     if let Some(lock) = GLOBAL_LOGGER.get() {
-        if let Ok(global) = lock.lock() {
+        if let Ok(global) = locks::lock(lock, Rank::GlobalLogger) {
             if let Some(logger) = global.as_ref() {
-                f(logger);
+                return Some(f(logger));
             }
         }
     }
+    None
+}
+
+// Logs through the global logger, or queues the call for `set_logger` if
+// there is none yet. The global lock is held while queueing so nothing can
+// slip in between the flush and the logger being installed.
+fn log_or_buffer<F>(f: F) -> LogStatus
+where F: FnOnce(&MessageLogger) -> LogStatus + Send + 'static
+{
+    let lock = GLOBAL_LOGGER.get_or_init(|| Mutex::new(None));
+    let Ok(global) = locks::lock(lock, Rank::GlobalLogger) else {
+        return LogStatus::Dropped;
+    };
+    if let Some(logger) = global.as_ref() {
+        return f(logger);
+    }
+
+    let Ok(mut pending) = locks::lock(&PENDING, Rank::Pending) else {
+        return LogStatus::Dropped;
+    };
+    let mut status = LogStatus::Accepted;
+    if pending.len() >= PENDING_CAPACITY {
+        pending.pop_front();
+        status = LogStatus::Evicted;
+    }
+    pending.push_back(Box::new(f));
+    status
+}
+
+pub fn log(message: String) -> LogStatus {
+    log_or_buffer(move |l| l.log(message))
+}
+
+pub fn log_styled(message: String, style: Style) -> LogStatus {
+    log_or_buffer(move |l| l.log_styled(message, style))
+}
+
+pub fn log_spans(segments: Vec<(String, Style)>) -> LogStatus {
+    log_or_buffer(move |l| l.log_spans(segments))
+}
+
+pub fn log_aligned(message: String, alignment: Alignment) -> LogStatus {
+    log_or_buffer(move |l| l.log_aligned(message, alignment))
+}
+
+pub fn log_pinned(level: Option<Level>, message: String) -> LogStatus {
+    log_or_buffer(move |l| l.log_pinned(level, message))
+}
+
+pub fn log_labeled(label: &str, message: String) -> LogStatus {
+    let label = label.to_string();
+    log_or_buffer(move |l| l.log_labeled(&label, message))
+}
+
+// Queued like a message so colors can be set up before the terminal starts.
+pub fn set_label_color(label: &str, color: Color) {
+    let label = label.to_string();
+    log_or_buffer(move |l| {
+        l.set_label_color(&label, color);
+        LogStatus::Accepted
+    });
+}
+
+pub fn log_updatable(message: &str) -> Option<u64> {
+    with_logger(|l| l.log_updatable(message)).flatten()
+}
+
+pub fn update(id: u64, message: &str) -> bool {
+    with_logger(|l| l.update(id, message)).unwrap_or(false)
+}
+
+pub fn fold_begin(label: &str) -> Option<u64> {
+    with_logger(|l| l.fold_begin(label)).flatten()
+}
+
+pub fn fold_end() {
+    with_logger(|l| l.fold_end());
+}
+
+pub fn set_fold_expanded(id: u64, expanded: bool) {
+    with_logger(|l| l.set_fold_expanded(id, expanded));
+}
+
+pub fn insert_at(index: usize, message: String) {
+    with_logger(|l| l.insert_at(index, message));
+}
+
+/// None if no logger is set yet.
+pub fn drain() -> Option<Vec<LogEntry>> {
+    with_logger(|l| l.drain())
+}
+
+pub fn scroll_by(lines: isize) {
+    with_logger(|l| l.scroll_by(lines));
+}
+
+pub fn scroll_to_top() {
+    with_logger(|l| l.scroll_to_top());
+}
+
+pub fn scroll_to_bottom() {
+    with_logger(|l| l.scroll_to_bottom());
+}
+
+pub fn set_log_file(path: &Path, max_size: u64) -> io::Result<()> {
+    with_logger(|l| l.set_log_file(path, max_size))
+        .unwrap_or_else(|| Err(io::Error::other("logger not initialized")))
+}
+
+pub fn close_log_file() {
+    with_logger(|l| l.close_log_file());
+}
+
+/// Returns false if no logger is set or stderr is a terminal.
+pub fn set_stderr_mirror(enabled: bool) -> bool {
+    with_logger(|l| l.set_stderr_mirror(enabled)).unwrap_or(false)
+}
+
+pub fn set_strip_incoming_timestamp(format: Option<&str>) -> bool {
+    with_logger(|l| l.set_strip_incoming_timestamp(format)).is_some()
+}
+
+/// See `MessageLogger::run_diagnostics`. Returns false if no logger is set.
+pub fn run_diagnostics() -> bool {
+    with_logger(|l| l.run_diagnostics()).is_some()
+}
+
+pub fn set_placeholder(text: &str) {
+    with_logger(|l| l.set_placeholder(text));
 }
 
-pub fn log(message: String) {
-    with_logger(|l| l.log(message));
+/// See `MessageLogger::prompt_once`. Returns false, dropping `answer`
+/// unanswered, if no logger is set.
+pub fn prompt_once<F>(prompt: &str, remember: bool, answer: F) -> bool
+where F: FnOnce(Option<String>) + Send + 'static
+{
+    with_logger(|l| l.prompt_once(prompt, remember, answer)).is_some()
+}
+
+pub fn cursor() -> usize {
+    with_logger(|l| l.cursor()).unwrap_or(0)
+}
+
+pub fn set_cursor(pos: usize) {
+    with_logger(|l| l.set_cursor(pos));
 }
 
-pub fn print_line(message: &str) {
-    log(message.to_string());
+pub fn print_line(message: &str) -> LogStatus {
+    log(message.to_string())
 }
 
-pub fn info(message: &str) {
-    with_logger(|l| l.info(message));
+pub fn info(message: &str) -> LogStatus {
+    let message = message.to_string();
+    log_or_buffer(move |l| l.info(&message))
 }
 
-pub fn error(message: &str) {
-    with_logger(|l| l.error(message));
+pub fn error(message: &str) -> LogStatus {
+    let message = message.to_string();
+    log_or_buffer(move |l| l.error(&message))
 }
 
-pub fn success(message: &str) {
-    with_logger(|l| l.success(message));
+pub fn success(message: &str) -> LogStatus {
+    let message = message.to_string();
+    log_or_buffer(move |l| l.success(&message))
 }
 
-pub fn warning(message: &str) {
-    with_logger(|l| l.warning(message));
+pub fn warning(message: &str) -> LogStatus {
+    let message = message.to_string();
+    log_or_buffer(move |l| l.warning(&message))
 }
 
-pub fn debug(message: &str) {
-    with_logger(|l| l.debug(message));
-}
\ No newline at end of file
+pub fn debug(message: &str) -> LogStatus {
+    let message = message.to_string();
+    log_or_buffer(move |l| l.debug(&message))
+}
+
+/// Internal trace output, shown at debug level only when verbose mode is on.
+pub fn verbose(message: &str) {
+    if is_verbose() {
+        debug(message);
+    }
+}
@@ -2,6 +2,13 @@ pub mod ui;
 pub mod repl_new;
 pub mod logger_new;
 pub mod api;
+pub mod keys;
+pub mod log_file;
+pub mod history_file;
+pub mod text;
+#[cfg(feature = "image-banner")]
+pub mod image_banner;
+mod locks;
 
 pub mod logger {
     pub use super::logger_new::*;
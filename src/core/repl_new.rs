@@ -1,75 +1,261 @@
-use crate::core::ui::{MessageLogger, TerminalUI};
+use crate::core::keys::encode_key;
+use crate::core::locks::{self, Rank};
+use crate::core::text::display_width;
+use crate::core::ui::{CommandHandler, Completion, TerminalUI, TransformedInput};
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
 use std::sync::{Mutex, OnceLock};
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+pub type StringCallback = Box<dyn Fn(&str) + Send + Sync>;
+/// Receives the input buffer and the cursor position in it, in chars.
+pub type CompletionCallback = Box<dyn Fn(&str, usize) + Send + Sync>;
+pub type KeyCallback = Box<dyn Fn(u32, u32) -> bool + Send + Sync>;
+pub type KeyObserverCallback = Box<dyn Fn(u32, u32) + Send + Sync>;
+pub type HeartbeatCallback = Box<dyn Fn() + Send + Sync>;
 
 pub static SHUTDOWN_SIGNAL: AtomicBool = AtomicBool::new(false);
-pub static JAVA_INPUT_CALLBACK: OnceLock<Box<dyn Fn(&str) + Send + Sync>> = OnceLock::new();
-pub static JAVA_TAB_CALLBACK: OnceLock<Box<dyn Fn(&str) + Send + Sync>> = OnceLock::new();
-pub static COMPLETION_CANDIDATES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+pub static JAVA_INPUT_CALLBACK: OnceLock<StringCallback> = OnceLock::new();
+pub static JAVA_TAB_CALLBACK: OnceLock<CompletionCallback> = OnceLock::new();
+pub static JAVA_KEY_CALLBACK: OnceLock<KeyCallback> = OnceLock::new();
+pub static JAVA_KEYPRESS_CALLBACK: OnceLock<KeyObserverCallback> = OnceLock::new();
+pub static JAVA_TRANSFORM_CALLBACK: OnceLock<StringCallback> = OnceLock::new();
+pub static JAVA_HEARTBEAT_CALLBACK: OnceLock<HeartbeatCallback> = OnceLock::new();
+// Milliseconds between heartbeats; 0 disables them. Read at start.
+pub static HEARTBEAT_INTERVAL_MS: AtomicU64 = AtomicU64::new(0);
+// Filled in by the transform callback while it runs; left empty, the line
+// goes through unchanged.
+pub static TRANSFORMED_INPUT: Mutex<Option<TransformedInput>> = Mutex::new(None);
+pub static COMPLETION_CANDIDATES: Mutex<Vec<Completion>> = Mutex::new(Vec::new());
+pub static BANNER_ENABLED: AtomicBool = AtomicBool::new(true);
+// Applied when the terminal starts; later changes go to the running UI too.
+pub static PLACEHOLDER: Mutex<String> = Mutex::new(String::new());
+// Read when the terminal starts and written back when it exits.
+pub static HISTORY_FILE: Mutex<Option<PathBuf>> = Mutex::new(None);
+// Lines for the normal screen buffer, printed once the TUI has left the
+// alternate screen.
+pub static FINAL_MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+// Only changed to false with FINAL_MESSAGES locked.
+pub static TERMINAL_RUNNING: AtomicBool = AtomicBool::new(false);
+
+const BANNER: [&str; 7] = [
+    "[RUST1] ██████╗ ██╗███████╗ ██████╗ ███████╗",
+    "[RUST2] ██╔══██╗██║██╔════╝██╔════╝ ██╔════╝",
+    "[RUST3] ██████╔╝██║█████╗  ██║  ███╗█████╗  ",
+    "[RUST4] ██╔══██╗██║██╔══╝  ██║   ██║██╔══╝  ",
+    "[RUST5] ██║  ██║██║███████╗╚██████╔╝███████╗",
+    "[RUST6] ╚═╝  ╚═╝╚═╝╚══════╝ ╚═════╝ ╚══════╝",
+    "",
+];
 
 pub struct Terminal {}
 
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Terminal {
     pub fn new() -> Self {
         Self {}
     }
 
+    /// Runs with the callbacks registered through the FFI.
     pub async fn run(&mut self) -> anyhow::Result<()> {
-        eprintln!("[RUST DEBUG] Terminal::run() starting");
-        let mut ui = TerminalUI::new();
-        eprintln!("[RUST DEBUG] TerminalUI created");
-        let logger = ui.get_message_logger();
+        self.run_with(FfiHandler).await
+    }
 
-        crate::core::logger::set_logger(logger.clone());
-        eprintln!("[RUST DEBUG] Logger set");
+    /// Runs with submitted lines and completion going to `handler` instead
+    /// of the FFI callbacks. The other FFI settings (banner, placeholder,
+    /// key callbacks, history file) still apply.
+    pub async fn run_with<H: CommandHandler>(&mut self, mut handler: H) -> anyhow::Result<()> {
+        let mut ui = TerminalUI::new();
+        TERMINAL_RUNNING.store(true, Ordering::Relaxed);
+        crate::core::logger::set_logger(ui.get_message_logger());
+        crate::core::logger::verbose("Terminal::run() starting, logger set");
 
-        self.add_banner(&logger);
-        eprintln!("[RUST DEBUG] Banner added");
+        if BANNER_ENABLED.load(Ordering::Relaxed) && !show_banner_image(&mut ui) {
+            show_banner();
+            crate::core::logger::verbose("Banner added");
+        }
         ui.set_prompt("rmc > ".to_string());
-        eprintln!("[RUST DEBUG] Prompt set, calling ui.run()");
-
-        ui.run(
-            move |raw_input| {
-                async move {
-                    if SHUTDOWN_SIGNAL.load(Ordering::Relaxed) {
-                        return Ok(true);
-                    }
-                    if let Some(callback) = JAVA_INPUT_CALLBACK.get() {
-                        callback(raw_input.trim());
-                    } else {
-                        crate::core::logger::error("Backend disconnected.");
-                    }
-                    Ok(false)
+        // Copied out first: the UI's own placeholder is a leaf lock too.
+        let placeholder = locks::lock(&PLACEHOLDER, Rank::Leaf).map(|p| p.clone()).unwrap_or_default();
+        ui.set_placeholder(&placeholder);
+        let history_file = locks::lock(&HISTORY_FILE, Rank::Leaf).ok().and_then(|path| path.clone());
+        if let Some(path) = history_file {
+            if let Err(e) = ui.set_history_file(&path) {
+                crate::core::logger::warning(&format!("History file {} not loaded: {}", path.display(), e));
+            }
+        }
+        ui.set_unhandled_key_handler(|key| {
+            match JAVA_KEY_CALLBACK.get() {
+                Some(callback) => {
+                    let (code, modifiers) = encode_key(&key);
+                    call_logged("Key", || callback(code, modifiers)).unwrap_or(false)
+                }
+                None => false,
+            }
+        });
+        ui.set_key_observer(|key| {
+            if let Some(callback) = JAVA_KEYPRESS_CALLBACK.get() {
+                let (code, modifiers) = encode_key(key);
+                call_logged("Keypress", || callback(code, modifiers));
+            }
+        });
+        ui.set_input_transform(transform_input);
+        let heartbeat_interval = HEARTBEAT_INTERVAL_MS.load(Ordering::Relaxed);
+        if heartbeat_interval > 0 {
+            ui.set_heartbeat(Duration::from_millis(heartbeat_interval), || {
+                if SHUTDOWN_SIGNAL.load(Ordering::Relaxed) {
+                    return;
                 }
-            },
-            move |current_buffer, _cursor_pos| {
-                if let Ok(mut candidates) = COMPLETION_CANDIDATES.lock() {
-                    candidates.clear();
+                if let Some(callback) = JAVA_HEARTBEAT_CALLBACK.get() {
+                    call_logged("Heartbeat", callback);
                 }
+            });
+        }
+        crate::core::logger::verbose("Prompt set, calling ui.run()");
 
-                if let Some(callback) = JAVA_TAB_CALLBACK.get() {
-                    callback(current_buffer);
-                }
+        let result = ui.run_with(&mut handler).await;
 
-                if let Ok(candidates) = COMPLETION_CANDIDATES.lock() {
-                    candidates.clone()
-                } else {
-                    Vec::new()
-                }
+        // Drained and printed under the lock so a concurrent
+        // terminal_print_final either queues before the drain or prints
+        // after the queued lines, never among them.
+        if let Ok(mut messages) = locks::lock(&FINAL_MESSAGES, Rank::Leaf) {
+            TERMINAL_RUNNING.store(false, Ordering::Relaxed);
+            for message in messages.drain(..) {
+                println!("{}", message);
             }
-        ).await?;
+        }
+        result?;
 
-        eprintln!("[RUST DEBUG] ui.run() completed");
+        crate::core::logger::verbose("ui.run() completed");
         Ok(())
     }
+}
+
+// Forwards to the callbacks registered through the FFI.
+struct FfiHandler;
+
+impl CommandHandler for FfiHandler {
+    async fn on_command(&mut self, line: String) -> Result<bool, String> {
+        if SHUTDOWN_SIGNAL.load(Ordering::Relaxed) {
+            return Ok(true);
+        }
+        match JAVA_INPUT_CALLBACK.get() {
+            Some(callback) => {
+                call_guarded("Input", || callback(line.trim()))?;
+                Ok(false)
+            }
+            None => Err("Backend disconnected.".to_string()),
+        }
+    }
+
+    fn complete(&mut self, buffer: &str, cursor: usize) -> Vec<Completion> {
+        if let Ok(mut candidates) = locks::lock(&COMPLETION_CANDIDATES, Rank::Leaf) {
+            candidates.clear();
+        }
+
+        if let Some(callback) = JAVA_TAB_CALLBACK.get() {
+            call_logged("Tab", || callback(buffer, cursor));
+        }
+
+        completion_candidates()
+    }
+}
+
+// Runs one of the JAVA_* callbacks, catching a panic instead of letting it
+// unwind through the UI loop and take the terminal down. That covers
+// closures set from Rust and the FFI forwarding code, but not the native
+// callbacks themselves: unwinding out of an `extern "C"` function can't be
+// caught, so those must not unwind at all (see `api::NativeCallback`).
+fn call_guarded<T>(name: &str, callback: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(callback))
+        .map_err(|payload| format!("{} callback panicked: {}", name, panic_message(&*payload)))
+}
+
+// `call_guarded`, logging the error, for callbacks whose failure the user
+// only needs to hear about.
+fn call_logged<T>(name: &str, callback: impl FnOnce() -> T) -> Option<T> {
+    call_guarded(name, callback)
+        .map_err(|e| crate::core::logger::error(&e))
+        .ok()
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown cause"
+    }
+}
+
+/// Snapshot of the candidates the tab callback has added so far.
+pub fn completion_candidates() -> Vec<Completion> {
+    match locks::lock(&COMPLETION_CANDIDATES, Rank::Leaf) {
+        Ok(candidates) => candidates.clone(),
+        Err(_) => Vec::new(),
+    }
+}
 
-    fn add_banner(&self, logger: &MessageLogger) {
-        logger.log("[RUST1] ██████╗ ██╗███████╗ ██████╗ ███████╗".to_string());
-        logger.log("[RUST2] ██╔══██╗██║██╔════╝██╔════╝ ██╔════╝".to_string());
-        logger.log("[RUST3] ██████╔╝██║█████╗  ██║  ███╗█████╗  ".to_string());
-        logger.log("[RUST4] ██╔══██╗██║██╔══╝  ██║   ██║██╔══╝  ".to_string());
-        logger.log("[RUST5] ██║  ██║██║███████╗╚██████╔╝███████╗".to_string());
-        logger.log("[RUST6] ╚═╝  ╚═╝╚═╝╚══════╝ ╚═════╝ ╚══════╝".to_string());
-        logger.log("".to_string());
+const COMPACT_BANNER: [&str; 2] = ["[RUST2] R-Term", ""];
+
+/// Logs the banner art, or a one-line title if the terminal is too narrow
+/// for it inside the message pane's borders.
+pub fn show_banner() {
+    let art_width = BANNER
+        .iter()
+        .map(|line| display_width(line.split_once(']').map_or(*line, |(_, art)| art)))
+        .max()
+        .unwrap_or(0);
+    // If the size can't be read, assume there is room.
+    let fits = crossterm::terminal::size().map_or(true, |(cols, _)| cols as usize >= art_width + 2);
+
+    let lines: &[&str] = if fits { &BANNER } else { &COMPACT_BANNER };
+    for line in lines {
+        crate::core::logger::log(line.to_string());
+    }
+}
+
+// Shows the logo image instead of the text banner, where it's enabled and
+// the terminal can display it. Returns false to fall back to the text.
+#[cfg(feature = "image-banner")]
+fn show_banner_image(ui: &mut TerminalUI) -> bool {
+    match crate::core::image_banner::BannerImage::from_env() {
+        Some(image) => {
+            ui.set_banner_image(image);
+            crate::core::logger::verbose("Banner image set");
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(not(feature = "image-banner"))]
+fn show_banner_image(_ui: &mut TerminalUI) -> bool {
+    false
+}
+
+fn transform_input(typed: &str) -> TransformedInput {
+    let unchanged = TransformedInput { line: typed.to_string(), history: None };
+    let Some(callback) = JAVA_TRANSFORM_CALLBACK.get() else {
+        return unchanged;
+    };
+
+    if let Ok(mut result) = locks::lock(&TRANSFORMED_INPUT, Rank::Leaf) {
+        *result = None;
+    }
+    if call_logged("Transform", || callback(typed)).is_none() {
+        return unchanged;
     }
-}
\ No newline at end of file
+    locks::lock(&TRANSFORMED_INPUT, Rank::Leaf)
+        .ok()
+        .and_then(|mut result| result.take())
+        .unwrap_or(unchanged)
+}
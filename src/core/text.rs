@@ -0,0 +1,525 @@
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+pub fn display_width(s: &str) -> usize {
+    UnicodeWidthStr::width(s)
+}
+
+/// Byte offset of the grapheme cluster before the one at byte `pos`, so a
+/// cursor never stops inside a cluster like "e\u{301}" or a ZWJ emoji.
+pub fn prev_grapheme(s: &str, pos: usize) -> usize {
+    s[..pos].grapheme_indices(true).next_back().map_or(0, |(i, _)| i)
+}
+
+/// Byte offset just past the grapheme cluster at byte `pos`.
+pub fn next_grapheme(s: &str, pos: usize) -> usize {
+    s[pos..].graphemes(true).next().map_or(s.len(), |g| pos + g.len())
+}
+
+/// Byte offset of the grapheme boundary at or before char index `chars`.
+pub fn grapheme_at_char(s: &str, chars: usize) -> usize {
+    let byte = s.char_indices().nth(chars).map_or(s.len(), |(i, _)| i);
+    if byte == s.len() {
+        return byte;
+    }
+    s.grapheme_indices(true).map(|(i, _)| i).take_while(|&i| i <= byte).last().unwrap_or(0)
+}
+
+/// Char index of the start of the word before `pos`: skips whitespace
+/// backwards, then the word itself.
+pub fn prev_word_start(s: &str, pos: usize) -> usize {
+    let chars: Vec<char> = s.chars().collect();
+    let mut pos = pos.min(chars.len());
+    while pos > 0 && chars[pos - 1].is_whitespace() {
+        pos -= 1;
+    }
+    while pos > 0 && !chars[pos - 1].is_whitespace() {
+        pos -= 1;
+    }
+    pos
+}
+
+/// Char index just past the end of the word at or after `pos`.
+pub fn next_word_end(s: &str, pos: usize) -> usize {
+    let chars: Vec<char> = s.chars().collect();
+    let mut pos = pos.min(chars.len());
+    while pos < chars.len() && chars[pos].is_whitespace() {
+        pos += 1;
+    }
+    while pos < chars.len() && !chars[pos].is_whitespace() {
+        pos += 1;
+    }
+    pos
+}
+
+/// Byte range of the whitespace-delimited word drawn at display `column`
+/// of `line`, e.g. for selecting it with a double click. None if the
+/// column is on whitespace or past the end. A wide character counts for
+/// each cell it covers.
+pub fn word_at_column(line: &str, column: usize) -> Option<Range<usize>> {
+    let graphemes: Vec<(usize, &str)> = line.grapheme_indices(true).collect();
+    let mut start_column = 0;
+    let hit = graphemes.iter().position(|(_, g)| {
+        start_column += display_width(g);
+        start_column > column
+    })?;
+    if graphemes[hit].1.trim().is_empty() {
+        return None;
+    }
+
+    let in_word = |(_, g): &&(usize, &str)| !g.trim().is_empty();
+    let first = hit - graphemes[..hit].iter().rev().take_while(in_word).count();
+    let last = hit + graphemes[hit + 1..].iter().take_while(in_word).count();
+    let (end, grapheme) = graphemes[last];
+    Some(graphemes[first].0..end + grapheme.len())
+}
+
+/// Standard padded base64, for escapes that carry binary or arbitrary
+/// text: inline images and OSC 52 clipboard writes.
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Replaces each tab with spaces up to the next multiple of `tab_width`
+/// columns from the start of the line, counting wide characters as the
+/// cells they occupy.
+pub fn expand_tabs(line: &str, tab_width: usize) -> String {
+    if !line.contains('\t') {
+        return line.to_string();
+    }
+    let tab_width = tab_width.max(1);
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+    for grapheme in line.graphemes(true) {
+        if grapheme == "\t" {
+            let spaces = tab_width - column % tab_width;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            expanded.push_str(grapheme);
+            column += display_width(grapheme);
+        }
+    }
+    expanded
+}
+
+/// Byte ranges of the `http://` and `https://` URLs in `line`. A URL runs
+/// to the next whitespace, quote or angle bracket, minus trailing
+/// punctuation and any unmatched closing bracket, so the URL in
+/// "(see https://example.org/a)." ends before the ')'.
+pub fn find_urls(line: &str) -> Vec<Range<usize>> {
+    let mut urls = Vec::new();
+    let mut from = 0;
+    while let Some(found) = line[from..].find("://") {
+        let separator = from + found;
+        from = separator + 3;
+
+        let start = line[..separator]
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !c.is_ascii_alphabetic())
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let scheme = &line[start..separator];
+        if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+            continue;
+        }
+        if !line[from..].starts_with(|c: char| c.is_alphanumeric() || c == '[') {
+            continue;
+        }
+
+        let end = line[from..]
+            .find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '`' | '<' | '>'))
+            .map_or(line.len(), |i| from + i);
+        let end = start + trim_url_end(&line[start..end]);
+        urls.push(start..end);
+        from = end;
+    }
+    urls
+}
+
+fn trim_url_end(url: &str) -> usize {
+    let mut url = url;
+    loop {
+        let unmatched = |open: char, close: char| {
+            url.ends_with(close) && url.matches(open).count() < url.matches(close).count()
+        };
+        if url.ends_with(['.', ',', ';', ':', '!', '?']) || unmatched('(', ')') || unmatched('[', ']') {
+            url = &url[..url.len() - 1];
+        } else {
+            return url.len();
+        }
+    }
+}
+
+/// Splits `line` into rows no wider than `width` terminal cells, breaking
+/// after whitespace where possible and inside a word only when the word
+/// alone is wider than a row. Widths are summed per grapheme, so a wide
+/// character that would straddle the edge moves to the next row.
+pub fn wrap_line(line: &str, width: usize) -> Vec<String> {
+    if width == 0 {
+        return vec![line.to_string()];
+    }
+
+    let mut rows = Vec::new();
+    let mut row = String::new();
+    let mut row_width = 0;
+
+    for word in line.split_word_bounds() {
+        let word_width = display_width(word);
+
+        if row_width + word_width <= width {
+            row.push_str(word);
+            row_width += word_width;
+            continue;
+        }
+
+        if word.trim().is_empty() {
+            // Whitespace at the break point is dropped rather than carried
+            // onto the next row.
+            rows.push(std::mem::take(&mut row));
+            row_width = 0;
+            continue;
+        }
+
+        if word_width <= width && row_width > 0 {
+            rows.push(std::mem::take(&mut row));
+            row.push_str(word);
+            row_width = word_width;
+            continue;
+        }
+
+        for grapheme in word.graphemes(true) {
+            let grapheme_width = display_width(grapheme);
+            if row_width + grapheme_width > width && row_width > 0 {
+                rows.push(std::mem::take(&mut row));
+                row_width = 0;
+            }
+            row.push_str(grapheme);
+            row_width += grapheme_width;
+        }
+    }
+
+    if !row.is_empty() || rows.is_empty() {
+        rows.push(row);
+    }
+    rows
+}
+
+/// Longest prefix shared by all `strings`, ending on a char boundary.
+pub fn common_prefix(strings: &[String]) -> &str {
+    let Some(first) = strings.first() else {
+        return "";
+    };
+    let mut len = first.len();
+    for s in &strings[1..] {
+        len = first
+            .char_indices()
+            .zip(s.chars())
+            .take_while(|((_, a), b)| a == b)
+            .last()
+            .map_or(0, |((i, a), _)| i + a.len_utf8())
+            .min(len);
+    }
+    &first[..len]
+}
+
+/// Scores `candidate` as a case-insensitive subsequence match of `query`,
+/// or None if it doesn't match. Runs of consecutive characters and matches
+/// at the start of a word score higher; shorter candidates win ties.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut chars = candidate.chars().enumerate();
+    let mut prev: Option<char> = None;
+    let mut last_match: Option<usize> = None;
+
+    for q in query.chars().flat_map(char::to_lowercase) {
+        loop {
+            let (i, c) = chars.next()?;
+            let at_word_start = prev.is_none_or(|p| !p.is_alphanumeric());
+            prev = Some(c);
+            if !c.to_lowercase().eq(std::iter::once(q)) {
+                continue;
+            }
+            score += 1;
+            if last_match.is_some_and(|m| m + 1 == i) {
+                score += 5;
+            }
+            if at_word_start {
+                score += 3;
+            }
+            last_match = Some(i);
+            break;
+        }
+    }
+
+    Some(score * 100 - candidate.chars().count() as i64)
+}
+
+/// Length in bytes of the timestamp `format` matches at the start of
+/// `line`, with the whitespace after it, or None if it doesn't match.
+/// `%Y` is four digits; `%m`, `%d`, `%H`, `%I` (12-hour), `%M` and `%S`
+/// two; `%f` one or more (fractions of a second); `%p` `AM` or `PM` in
+/// either case; `%%` a literal `%`. Anything else in the format must
+/// appear as is, e.g. `[%H:%M:%S.%f]` or `%I:%M %p`.
+pub fn timestamp_prefix(line: &str, format: &str) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut pos = 0;
+    let digits = |pos: &mut usize, min: usize, max: usize| {
+        let count = bytes[*pos..].iter().take(max).take_while(|b| b.is_ascii_digit()).count();
+        *pos += count;
+        (count >= min).then_some(())
+    };
+
+    let mut spec = format.chars();
+    while let Some(c) = spec.next() {
+        let literal = match c {
+            '%' => match spec.next()? {
+                'Y' => { digits(&mut pos, 4, 4)?; continue; }
+                'm' | 'd' | 'H' | 'I' | 'M' | 'S' => { digits(&mut pos, 2, 2)?; continue; }
+                'p' => {
+                    let meridiem = line.get(pos..pos + 2)?;
+                    if !meridiem.eq_ignore_ascii_case("AM") && !meridiem.eq_ignore_ascii_case("PM") {
+                        return None;
+                    }
+                    pos += 2;
+                    continue;
+                }
+                'f' => { digits(&mut pos, 1, usize::MAX)?; continue; }
+                '%' => '%',
+                _ => return None,
+            },
+            c => c,
+        };
+        pos = line.len() - line[pos..].strip_prefix(literal)?.len();
+    }
+    Some(line.len() - line[pos..].trim_start().len())
+}
+
+/// A local date and time, as `format_timestamp` writes it out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+    pub millis: u32,
+}
+
+/// `time` written out as `format`, with the fields `timestamp_prefix`
+/// reads; `%f` is milliseconds and `%p` is `AM` or `PM`. Fails on a `%`
+/// that starts none of them.
+pub fn format_timestamp(format: &str, time: &DateTime) -> Result<String, String> {
+    let mut out = String::with_capacity(format.len() + 8);
+    let mut spec = format.chars();
+    while let Some(c) = spec.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match spec.next() {
+            Some('Y') => out.push_str(&format!("{:04}", time.year)),
+            Some('m') => out.push_str(&format!("{:02}", time.month)),
+            Some('d') => out.push_str(&format!("{:02}", time.day)),
+            Some('H') => out.push_str(&format!("{:02}", time.hour)),
+            Some('I') => out.push_str(&format!("{:02}", (time.hour + 11) % 12 + 1)),
+            Some('p') => out.push_str(if time.hour < 12 { "AM" } else { "PM" }),
+            Some('M') => out.push_str(&format!("{:02}", time.minute)),
+            Some('S') => out.push_str(&format!("{:02}", time.second)),
+            Some('f') => out.push_str(&format!("{:03}", time.millis)),
+            Some('%') => out.push('%'),
+            Some(other) => return Err(format!("unknown field %{} in timestamp format", other)),
+            None => return Err("timestamp format ends in a lone %".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_pads_partial_groups() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn timestamps_are_formatted_field_by_field() {
+        let time = DateTime { year: 2024, month: 3, day: 9, hour: 7, minute: 5, second: 4, millis: 42 };
+        assert_eq!(format_timestamp("%H:%M:%S", &time).unwrap(), "07:05:04");
+        assert_eq!(format_timestamp("[%Y-%m-%d %H:%M:%S.%f] 100%%", &time).unwrap(), "[2024-03-09 07:05:04.042] 100%");
+        assert!(format_timestamp("%q", &time).is_err());
+        assert!(format_timestamp("%H%", &time).is_err());
+    }
+
+    #[test]
+    fn twelve_hour_timestamps_round_trip() {
+        let format = "[%I:%M:%S %p]";
+        for (hour, written) in [(0, "[12:05:04 AM]"), (7, "[07:05:04 AM]"), (12, "[12:05:04 PM]"), (23, "[11:05:04 PM]")] {
+            let time = DateTime { hour, minute: 5, second: 4, ..DateTime::default() };
+            let line = format!("{} ready", format_timestamp(format, &time).unwrap());
+            assert_eq!(line, format!("{} ready", written));
+            assert_eq!(timestamp_prefix(&line, format), Some(written.len() + 1));
+        }
+        assert_eq!(timestamp_prefix("[07:05:04 pm] ready", format), Some(14));
+        assert_eq!(timestamp_prefix("[07:05:04 XM] ready", format), None);
+    }
+
+    #[test]
+    fn wrap_line_breaks_at_whitespace() {
+        assert_eq!(wrap_line("hello wide world", 10), vec!["hello wide", "world"]);
+    }
+
+    #[test]
+    fn wrap_line_counts_wide_characters_as_two_columns() {
+        // Five CJK characters take ten cells.
+        assert_eq!(wrap_line("日本語日本", 10), vec!["日本語日本"]);
+        assert_eq!(wrap_line("日本語日本語", 10), vec!["日本語日本", "語"]);
+    }
+
+    #[test]
+    fn wrap_line_moves_wide_character_that_would_straddle_the_edge() {
+        // "abc" plus three wide chars is 9 cells; the fourth would need
+        // cells 10 and 11 of a 10-cell row.
+        assert_eq!(wrap_line("abc日本語日", 10), vec!["abc日本語", "日"]);
+    }
+
+    #[test]
+    fn wrap_line_mixes_ascii_and_wide_words() {
+        // Ideographs are separate break opportunities, like in most
+        // terminals and browsers.
+        let rows = wrap_line("ok 日本語 done", 8);
+        assert_eq!(rows, vec!["ok 日本", "語 done"]);
+        assert!(rows.iter().all(|r| display_width(r) <= 8));
+    }
+
+    #[test]
+    fn fuzzy_score_matches_subsequences_only() {
+        assert!(fuzzy_score("gco", "git checkout").is_some());
+        assert!(fuzzy_score("GIT", "git status").is_some());
+        assert_eq!(fuzzy_score("xyz", "git status"), None);
+        assert_eq!(fuzzy_score("tig", "git"), None);
+    }
+
+    #[test]
+    fn fuzzy_score_prefers_consecutive_and_word_start_matches() {
+        let consecutive = fuzzy_score("stat", "git status").unwrap();
+        let scattered = fuzzy_score("stat", "set a timer").unwrap();
+        assert!(consecutive > scattered);
+
+        let word_start = fuzzy_score("c", "git commit").unwrap();
+        let inner = fuzzy_score("c", "git pick").unwrap();
+        assert!(word_start > inner);
+    }
+
+    #[test]
+    fn common_prefix_stops_at_first_difference() {
+        let words = |w: &[&str]| w.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(common_prefix(&words(&["connect", "config", "conf"])), "con");
+        assert_eq!(common_prefix(&words(&["status"])), "status");
+        assert_eq!(common_prefix(&words(&["über", "übel"])), "übe");
+        assert_eq!(common_prefix(&words(&["a", "b"])), "");
+        assert_eq!(common_prefix(&[]), "");
+    }
+
+    #[test]
+    fn expand_tabs_aligns_to_stops() {
+        assert_eq!(expand_tabs("a\tb", 4), "a   b");
+        assert_eq!(expand_tabs("abcd\te", 4), "abcd    e");
+        assert_eq!(expand_tabs("\t\tx", 2), "    x");
+        assert_eq!(expand_tabs("no tabs", 4), "no tabs");
+    }
+
+    #[test]
+    fn expand_tabs_mixes_spaces_and_tabs() {
+        assert_eq!(expand_tabs("ab \tc", 4), "ab  c");
+        assert_eq!(expand_tabs("name\tvalue\nx", 8), "name    value\nx");
+    }
+
+    #[test]
+    fn expand_tabs_counts_wide_characters_as_two_columns() {
+        // "日本" takes four cells, so the tab only needs four more to reach 8.
+        assert_eq!(expand_tabs("日本\tx", 8), "日本    x");
+        assert_eq!(display_width(&expand_tabs("日本語\tx", 4)), 9);
+    }
+
+    fn urls(line: &str) -> Vec<&str> {
+        find_urls(line).into_iter().map(|r| &line[r]).collect()
+    }
+
+    #[test]
+    fn find_urls_stops_at_whitespace_and_trailing_punctuation() {
+        assert_eq!(urls("see https://example.org/a?b=1, then"), vec!["https://example.org/a?b=1"]);
+        assert_eq!(urls("(docs at http://x.io/wiki/Foo_(bar))."), vec!["http://x.io/wiki/Foo_(bar)"]);
+        assert_eq!(urls("<https://a.b> and \"http://c.d\""), vec!["https://a.b", "http://c.d"]);
+    }
+
+    #[test]
+    fn word_at_column_maps_cells_back_to_words() {
+        let line = "open 日本.txt now";
+        let word = |column| word_at_column(line, column).map(|r| &line[r]);
+        assert_eq!(word(0), Some("open"));
+        assert_eq!(word(4), None);
+        // "日" covers cells 5 and 6, "本" cells 7 and 8.
+        assert_eq!(word(6), Some("日本.txt"));
+        assert_eq!(word(8), Some("日本.txt"));
+        assert_eq!(word(16), Some("now"));
+        assert_eq!(word(17), None);
+    }
+
+    #[test]
+    fn grapheme_steps_skip_whole_clusters() {
+        // A family emoji joined with ZWJs, then "é" as e + combining acute.
+        let s = "a👨\u{200d}👩\u{200d}👧e\u{301}b";
+        let family = 1..1 + "👨\u{200d}👩\u{200d}👧".len();
+        let accent = family.end..family.end + 3;
+        assert_eq!(next_grapheme(s, 0), family.start);
+        assert_eq!(next_grapheme(s, family.start), family.end);
+        assert_eq!(next_grapheme(s, family.end), accent.end);
+        assert_eq!(prev_grapheme(s, s.len()), accent.end);
+        assert_eq!(prev_grapheme(s, accent.end), accent.start);
+        assert_eq!(prev_grapheme(s, accent.start), family.start);
+        assert_eq!(prev_grapheme(s, 0), 0);
+        // Char 3 is the first ZWJ, inside the family.
+        assert_eq!(grapheme_at_char(s, 3), family.start);
+        assert_eq!(grapheme_at_char(s, 99), s.len());
+        assert_eq!(display_width("e\u{301}"), 1);
+    }
+
+    #[test]
+    fn find_urls_ignores_non_url_text() {
+        assert!(urls("ratio 3://4, path C://dir").is_empty());
+        assert!(urls("xhttps://example.org").is_empty());
+        assert!(urls("https:// nothing").is_empty());
+        assert!(urls("plain text").is_empty());
+    }
+
+    #[test]
+    fn timestamp_prefix_matches_the_format_and_following_space() {
+        let format = "[%Y-%m-%d %H:%M:%S.%f]";
+        assert_eq!(timestamp_prefix("[2024-05-01 12:30:05.123] ready", format), Some(26));
+        assert_eq!(timestamp_prefix("[2024-05-01 12:30:05] ready", format), None);
+        assert_eq!(timestamp_prefix("ready [2024-05-01 12:30:05.1]", format), None);
+        assert_eq!(timestamp_prefix("12:30 100%", "%H:%M"), Some(6));
+    }
+}
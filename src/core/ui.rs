@@ -1,40 +1,108 @@
+use crate::core::history_file::{self, HISTORY_FILE_CAPACITY};
+#[cfg(feature = "image-banner")]
+use crate::core::image_banner::BannerImage;
+use crate::core::keys::{key_label, word_motion, Action, EditAction, WordMotion};
+use crate::core::locks::{self, Rank};
+use crate::core::log_file::{LogFile, StderrMirror};
+use crate::core::text::{
+    base64, common_prefix, display_width, expand_tabs, find_urls, format_timestamp, fuzzy_score, grapheme_at_char,
+    next_grapheme, next_word_end, prev_grapheme, prev_word_start, timestamp_prefix, word_at_column, wrap_line,
+    DateTime,
+};
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton,
+        MouseEvent, MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 
-struct Cleanup;
+struct Cleanup {
+    alternate_screen: bool,
+    mouse_capture: bool,
+}
 
 impl Drop for Cleanup {
     fn drop(&mut self) {
         let _ = disable_raw_mode();
-        let _ = execute!(std::io::stdout(), LeaveAlternateScreen);
+        if self.mouse_capture {
+            let _ = execute!(std::io::stdout(), DisableMouseCapture);
+        }
+        if self.alternate_screen {
+            let _ = execute!(std::io::stdout(), LeaveAlternateScreen);
+        }
     }
 }
 use ratatui::{
-    backend::CrosstermBackend,
-    layout::{Constraint, Direction, Layout},
-    style::{Color, Style},
+    backend::{Backend, CrosstermBackend},
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Position, Rect},
+    style::{Color, Modifier, Style},
     text::{Line, Span},
-    widgets::{Block, Borders, List, ListItem, Paragraph},
+    widgets::{
+        Block, Borders, Clear, List, ListItem, ListState, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState,
+    },
     Frame, Terminal,
 };
-use std::collections::VecDeque;
-use std::io;
-use std::sync::{Arc, Mutex};
+use std::cell::RefCell;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::io::{self, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::broadcast;
 
-fn strip_ansi_codes(s: &str) -> String {
+// Longest CSI sequence we are willing to scan for a final byte. Anything
+// longer is treated as malformed and its text is kept.
+const MAX_CSI_LEN: usize = 32;
+
+pub(crate) fn strip_ansi_codes(s: &str) -> String {
+    scan_ansi(s, |_, _| {})
+}
+
+// Removes CSI sequences from `s`, calling `on_sequence` with each one's
+// parameters and final byte (e.g. "31m") and the length of the text kept
+// so far.
+fn scan_ansi(s: &str, mut on_sequence: impl FnMut(&str, usize)) -> String {
     let mut result = String::new();
     let mut chars = s.chars();
     while let Some(c) = chars.next() {
         if c == '\x1b' {
             if let Some('[') = chars.next() {
-                while let Some(c) = chars.next() {
-                    if c.is_ascii_alphabetic() {
+                let mut lookahead = chars.clone();
+                let mut len = 0;
+                let mut terminated = false;
+                let mut truncated = true;
+                let mut sequence = String::new();
+                for c in lookahead.by_ref() {
+                    len += 1;
+                    sequence.push(c);
+                    if ('\x40'..='\x7e').contains(&c) {
+                        terminated = true;
+                        break;
+                    }
+                    // Parameter and intermediate bytes only; anything else
+                    // means this wasn't a real CSI sequence.
+                    if !('\x20'..='\x3f').contains(&c) || len >= MAX_CSI_LEN {
+                        truncated = false;
                         break;
                     }
                 }
+
+                if terminated {
+                    on_sequence(&sequence, result.len());
+                    chars = lookahead;
+                } else if truncated {
+                    // Sequence cut off at the end of the input: drop it.
+                    break;
+                } else {
+                    result.push('[');
+                }
             }
         } else {
             result.push(c);
@@ -43,52 +111,406 @@ fn strip_ansi_codes(s: &str) -> String {
     result
 }
 
-fn parse_message_type(msg: &str) -> (String, Color) {
-    if msg.starts_with("[RUST1]") {
-        (msg.trim_start_matches("[RUST1]").to_string(), Color::Rgb(204, 85, 0))
-    } else if msg.starts_with("[RUST2]") {
-        (msg.trim_start_matches("[RUST2]").to_string(), Color::Rgb(255, 102, 0))
-    } else if msg.starts_with("[RUST3]") {
-        (msg.trim_start_matches("[RUST3]").to_string(), Color::Rgb(255, 136, 0))
-    } else if msg.starts_with("[RUST4]") {
-        (msg.trim_start_matches("[RUST4]").to_string(), Color::Rgb(204, 102, 0))
-    } else if msg.starts_with("[RUST5]") {
-        (msg.trim_start_matches("[RUST5]").to_string(), Color::Rgb(170, 85, 0))
-    } else if msg.starts_with("[RUST6]") {
-        (msg.trim_start_matches("[RUST6]").to_string(), Color::Rgb(136, 68, 0))
-    } else if msg.starts_with("[RUST7]") {
-        (msg.trim_start_matches("[RUST7]").to_string(), Color::Rgb(119, 51, 0))
-    } else if msg.starts_with("[ERROR]") || msg.starts_with("✗") {
-        (msg.to_string(), Color::Red)
-    } else if msg.starts_with("[✓]") || msg.starts_with("[SUCCESS]") {
-        (msg.to_string(), Color::Green)
-    } else if msg.starts_with("[INFO]") || msg.starts_with("ℹ") {
-        (msg.to_string(), Color::Cyan)
-    } else if msg.starts_with("[WARNING]") || msg.starts_with("⚠") {
-        (msg.to_string(), Color::Yellow)
-    } else if msg.starts_with("[DEBUG]") {
-        (msg.to_string(), Color::Magenta)
-    } else if msg.starts_with("Username:") || msg.starts_with("UUID:") {
-        (msg.to_string(), Color::LightBlue)
-    } else if msg.starts_with("Connecting") || msg.starts_with("Starting") {
-        (msg.to_string(), Color::LightGreen)
-    } else if msg.starts_with("Waiting") || msg.starts_with("Loading") {
-        (msg.to_string(), Color::LightYellow)
+// Text of `s` without escape sequences, and the ranges of it that SGR
+// codes styled, as patches over the line's own style. Reset codes (0, 39,
+// 49, 22 and so on) go back to that style rather than to a color of their
+// own.
+fn sgr_runs(s: &str) -> (String, Vec<(Range<usize>, Style)>) {
+    let mut runs: Vec<(Range<usize>, Style)> = Vec::new();
+    let mut current = Style::default();
+    let mut start = 0;
+    let text = scan_ansi(s, |sequence, pos| {
+        let Some(params) = sequence.strip_suffix('m') else {
+            return;
+        };
+        // Codes that change nothing, or that aren't understood, don't
+        // split the run.
+        let next = apply_sgr(current, params);
+        if next == current {
+            return;
+        }
+        if current != Style::default() && pos > start {
+            runs.push((start..pos, current));
+        }
+        current = next;
+        start = pos;
+    });
+    if current != Style::default() && text.len() > start {
+        runs.push((start..text.len(), current));
+    }
+    (text, runs)
+}
+
+// The 16 colors of SGR 30-37 and 90-97, in order.
+const ANSI_COLORS: [Color; 16] = [
+    Color::Black, Color::Red, Color::Green, Color::Yellow,
+    Color::Blue, Color::Magenta, Color::Cyan, Color::Gray,
+    Color::DarkGray, Color::LightRed, Color::LightGreen, Color::LightYellow,
+    Color::LightBlue, Color::LightMagenta, Color::LightCyan, Color::White,
+];
+
+fn apply_sgr(mut style: Style, params: &str) -> Style {
+    let codes: Vec<u16> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+    let mut codes = codes.iter().copied();
+    while let Some(code) = codes.next() {
+        match code {
+            0 => style = Style::default(),
+            1 => style = style.add_modifier(Modifier::BOLD),
+            2 => style = style.add_modifier(Modifier::DIM),
+            3 => style = style.add_modifier(Modifier::ITALIC),
+            4 => style = style.add_modifier(Modifier::UNDERLINED),
+            5 => style = style.add_modifier(Modifier::SLOW_BLINK),
+            7 => style = style.add_modifier(Modifier::REVERSED),
+            9 => style = style.add_modifier(Modifier::CROSSED_OUT),
+            22 => style.add_modifier.remove(Modifier::BOLD | Modifier::DIM),
+            23 => style.add_modifier.remove(Modifier::ITALIC),
+            24 => style.add_modifier.remove(Modifier::UNDERLINED),
+            25 => style.add_modifier.remove(Modifier::SLOW_BLINK),
+            27 => style.add_modifier.remove(Modifier::REVERSED),
+            29 => style.add_modifier.remove(Modifier::CROSSED_OUT),
+            30..=37 => style.fg = Some(ANSI_COLORS[code as usize - 30]),
+            38 => style.fg = extended_color(&mut codes).or(style.fg),
+            39 => style.fg = None,
+            40..=47 => style.bg = Some(ANSI_COLORS[code as usize - 40]),
+            48 => style.bg = extended_color(&mut codes).or(style.bg),
+            49 => style.bg = None,
+            90..=97 => style.fg = Some(ANSI_COLORS[code as usize - 90 + 8]),
+            100..=107 => style.bg = Some(ANSI_COLORS[code as usize - 100 + 8]),
+            _ => {}
+        }
+    }
+    style
+}
+
+// The color after a 38 or 48: `5;n` for the 256-color palette, `2;r;g;b`
+// for RGB.
+fn extended_color(codes: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    match codes.next()? {
+        5 => Some(Color::Indexed(codes.next()?.min(255) as u8)),
+        2 => {
+            let mut channel = || codes.next().map(|c| c.min(255) as u8);
+            Some(Color::Rgb(channel()?, channel()?, channel()?))
+        }
+        _ => None,
+    }
+}
+
+// Shades of orange for the banner, by `[RUSTn]` tag.
+const BANNER_TAGS: [(&str, Color); 7] = [
+    ("RUST1", Color::Rgb(204, 85, 0)),
+    ("RUST2", Color::Rgb(255, 102, 0)),
+    ("RUST3", Color::Rgb(255, 136, 0)),
+    ("RUST4", Color::Rgb(204, 102, 0)),
+    ("RUST5", Color::Rgb(170, 85, 0)),
+    ("RUST6", Color::Rgb(136, 68, 0)),
+    ("RUST7", Color::Rgb(119, 51, 0)),
+];
+
+fn banner_tag(line: &str) -> Option<&'static str> {
+    let inner = line.strip_prefix('[')?;
+    BANNER_TAGS
+        .iter()
+        .map(|(tag, _)| *tag)
+        .find(|tag| inner.strip_prefix(tag).is_some_and(|rest| rest.starts_with(']')))
+}
+
+fn tag_color(tag: &str) -> Color {
+    BANNER_TAGS
+        .iter()
+        .find(|(t, _)| *t == tag)
+        .map(|(_, color)| *color)
+        .unwrap_or(Color::White)
+}
+
+// Colors for status lines the backend prints without a level prefix.
+fn hint_color(line: &str) -> Color {
+    if line.starts_with("Username:") || line.starts_with("UUID:") {
+        Color::LightBlue
+    } else if line.starts_with("Connecting") || line.starts_with("Starting") {
+        Color::LightGreen
+    } else if line.starts_with("Waiting") || line.starts_with("Loading") {
+        Color::LightYellow
     } else {
-        (msg.to_string(), Color::White)
+        Color::White
+    }
+}
+
+// Text and style a stored line is drawn with. An explicit style takes
+// precedence over the color from the entry's tag or level, and keeps the
+// text as logged.
+fn entry_parts(entry: &LogEntry) -> (String, Style) {
+    let cleaned = strip_ansi_codes(&entry.text);
+    if let Some(style) = entry.style {
+        return (cleaned, style);
+    }
+
+    let (text, color) = match &entry.tag {
+        Some(tag) => {
+            let prefix = format!("[{}]", tag);
+            let text = cleaned.strip_prefix(&prefix).unwrap_or(&cleaned).to_string();
+            (text, tag_color(tag))
+        }
+        None => {
+            let color = entry.level.map_or_else(|| hint_color(&cleaned), Level::color);
+            (cleaned, color)
+        }
+    };
+    (text, Style::default().fg(color))
+}
+
+// `base` with the parts covered by `top` cut out, merged with `top`. Both
+// are sorted lists of disjoint ranges, and so is the result.
+fn overlay(base: Vec<(Range<usize>, Style)>, top: Vec<(Range<usize>, Style)>) -> Vec<(Range<usize>, Style)> {
+    let mut merged = Vec::new();
+    for (range, style) in base {
+        let mut pos = range.start;
+        for (cut, _) in top.iter().filter(|(t, _)| t.start < range.end && t.end > range.start) {
+            if cut.start > pos {
+                merged.push((pos..cut.start, style));
+            }
+            pos = pos.max(cut.end);
+        }
+        if pos < range.end {
+            merged.push((pos..range.end, style));
+        }
+    }
+    merged.extend(top);
+    merged.sort_by_key(|(range, _)| range.start);
+    merged
+}
+
+// Splits `row`, which starts at byte `offset` of its message, so the parts
+// inside `highlights` (sorted, disjoint ranges over the whole message) get
+// their own style.
+fn highlight_spans(row: String, offset: usize, highlights: &[(Range<usize>, Style)], style: Style) -> Vec<Span<'static>> {
+    let end = offset + row.len();
+    let mut spans = Vec::new();
+    let mut pos = offset;
+    for (range, highlight) in highlights.iter().filter(|(r, _)| r.start < end && r.end > offset) {
+        let (from, to) = (range.start.max(offset), range.end.min(end));
+        if from > pos {
+            spans.push(Span::styled(row[pos - offset..from - offset].to_string(), style));
+        }
+        spans.push(Span::styled(row[from - offset..to - offset].to_string(), *highlight));
+        pos = to;
+    }
+    if spans.is_empty() {
+        return vec![Span::styled(row, style)];
     }
+    if pos < end {
+        spans.push(Span::styled(row[pos - offset..].to_string(), style));
+    }
+    spans
+}
+
+// Approximate xterm RGB values for the named colors, so they can be dimmed.
+fn dim_color(color: Color, factor: f32) -> Color {
+    let (r, g, b) = match color {
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Black => (0, 0, 0),
+        Color::Red => (205, 0, 0),
+        Color::Green => (0, 205, 0),
+        Color::Yellow => (205, 205, 0),
+        Color::Blue => (0, 0, 238),
+        Color::Magenta => (205, 0, 205),
+        Color::Cyan => (0, 205, 205),
+        Color::DarkGray => (127, 127, 127),
+        Color::LightRed => (255, 0, 0),
+        Color::LightGreen => (0, 255, 0),
+        Color::LightYellow => (255, 255, 0),
+        Color::LightBlue => (92, 92, 255),
+        Color::LightMagenta => (255, 0, 255),
+        Color::LightCyan => (0, 255, 255),
+        Color::White => (255, 255, 255),
+        _ => (229, 229, 229),
+    };
+    let scale = |c: u8| (c as f32 * factor).round() as u8;
+    Color::Rgb(scale(r), scale(g), scale(b))
 }
 
 const MAX_MESSAGES: usize = 1000;
+// Leaves most of the buffer evictable, so trimming always has lines to take.
+const MAX_PINNED: usize = MAX_MESSAGES / 4;
+const ERROR_PANEL_WIDTH: u16 = 40;
+const MAX_COMPLETION_ROWS: u16 = 8;
+const BADGE_WIDTH: usize = 3;
+const DEFAULT_TAB_WIDTH: usize = 8;
+// Upper bound on queued events handled between two frames, so the screen
+// still updates during a very long paste.
+const MAX_EVENTS_PER_FRAME: usize = 4096;
+const DEFAULT_MAX_FPS: u32 = 30;
+// Lines one notch of the mouse wheel scrolls.
+const WHEEL_SCROLL_LINES: isize = 3;
+// Longest gap between clicks that still counts as a double or triple click.
+const MULTI_CLICK_TIME: Duration = Duration::from_millis(400);
+// How long the loop waits for input before redrawing anyway.
+const IDLE_TICK: Duration = Duration::from_millis(50);
+// Brightness of the top row when recency fade is on.
+const FADE_FLOOR: f32 = 0.4;
+// Lines a subscriber may fall behind before it starts seeing `Lagged`.
+const SUBSCRIBER_CAPACITY: usize = 256;
+// Title spinner shown while lines keep arriving; each frame lasts
+// SPINNER_FRAME_TIME, and it stops ACTIVITY_WINDOW after the newest line.
+const SPINNER_FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+const SPINNER_FRAME_TIME: Duration = Duration::from_millis(100);
+const ACTIVITY_WINDOW: Duration = Duration::from_millis(500);
+// How long exit waits for the history file to be written.
+const HISTORY_SAVE_TIMEOUT: Duration = Duration::from_secs(2);
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%H:%M:%S";
+const TIMESTAMP_STYLE: Style = Style::new().fg(Color::DarkGray).add_modifier(Modifier::DIM);
+// For `log_labeled` labels that have no color set and don't name a level.
+const DEFAULT_LABEL_COLOR: Color = Color::LightMagenta;
+// Height of the banner image, as tall as the text banner's art.
+#[cfg(feature = "image-banner")]
+const SPLASH_ROWS: u16 = 6;
+// Numbered lines `run_diagnostics` logs to fill the pane and test scrolling.
+const DIAGNOSTIC_SCROLL_LINES: usize = 60;
+// How long a key hint ("unbound key", "press again to quit") stays in the
+// input box title, and so how long a quit confirmation waits.
+const KEY_HINT_TIME: Duration = Duration::from_secs(2);
+// When set (to anything), `TerminalUI::new` starts with the alternate
+// screen disabled. See `set_alternate_screen`.
+const NO_ALT_SCREEN_ENV: &str = "RIEGE_XTERM_NO_ALT_SCREEN";
+
+type KeyHandler = Box<dyn FnMut(KeyEvent) -> bool>;
+type KeyObserver = Box<dyn FnMut(&KeyEvent)>;
+type CopyHandler = Box<dyn FnMut(&str)>;
+type InputTransform = Box<dyn FnMut(&str) -> TransformedInput>;
+type PromptAnswer = Box<dyn FnOnce(Option<String>) + Send>;
+
+// A question from `MessageLogger::prompt_once`.
+struct PromptOnce {
+    prompt: String,
+    remember: bool,
+    answer: PromptAnswer,
+}
+
+// The banner image, shown above the message pane until the first key.
+#[cfg(feature = "image-banner")]
+struct Splash {
+    image: BannerImage,
+    // Where the last frame left room for it, and where it was drawn.
+    area: std::cell::Cell<Option<Rect>>,
+    drawn: Option<Rect>,
+    ended: bool,
+}
+
+struct Heartbeat {
+    interval: Duration,
+    last: Instant,
+    callback: Box<dyn FnMut()>,
+}
 
 pub struct TerminalUI {
-    messages: Arc<Mutex<VecDeque<String>>>,
+    messages: Arc<Mutex<VecDeque<LogEntry>>>,
     input: String,
+    // Byte offset into `input`, always on a grapheme cluster boundary.
     cursor_position: usize,
+    // The text last cut with an `EditAction`, for `EditAction::Paste`.
+    kill_buffer: String,
     prompt: String,
-    scroll_offset: usize,
+    prompt_style: Style,
+    // Drawn between the prompt and the input, but not part of the prompt.
+    prompt_separator: String,
+    prompt_separator_style: Style,
+    input_style: Style,
+    // Most text rows the input grows to when it is a multi-line editor;
+    // None for the usual single line.
+    input_editor: Option<u16>,
+    view: Arc<ViewState>,
+    trim: Arc<Mutex<TrimState>>,
+    subscribers: broadcast::Sender<LogEntry>,
+    log_file: Arc<Mutex<Option<LogFile>>>,
+    stderr_mirror: Arc<AtomicBool>,
+    // Started the first time the mirror is turned on.
+    stderr_writer: Arc<OnceLock<StderrMirror>>,
+    incoming_timestamp: Arc<Mutex<Option<String>>>,
+    placeholder: Arc<Mutex<String>>,
+    labels: Arc<Mutex<HashMap<String, Color>>>,
+    folds: Arc<Mutex<FoldState>>,
+    // Asked through a MessageLogger, oldest first; the loop moves the
+    // newest to `prompt_once`.
+    prompt_once_requests: Arc<Mutex<Vec<PromptOnce>>>,
+    prompt_once: Option<PromptOnce>,
     history: Vec<String>,
     history_index: usize,
+    history_file: Option<PathBuf>,
+    // Parallel record of submitted commands with their outcome, when
+    // enabled.
+    annotated_history: Option<Vec<HistoryEntry>>,
+    unhandled_key_handler: Option<KeyHandler>,
+    key_observer: Option<KeyObserver>,
+    heartbeat: Option<Heartbeat>,
+    input_transform: Option<InputTransform>,
+    function_keys: BTreeMap<u8, Action>,
+    edit_keys: Vec<((KeyCode, KeyModifiers), EditAction)>,
+    history_search_key: (KeyCode, KeyModifiers),
+    completion_behavior: CompletionBehavior,
+    empty_submit: EmptySubmit,
+    empty_tab: EmptyTab,
+    // Candidates shown above the input after an ambiguous Tab, until the
+    // next key.
+    completion_list: Vec<Completion>,
+    completion_selection: usize,
+    // Candidates Tab is stepping through with `CompletionBehavior::Cycle`,
+    // and which one is in the input; listed like `completion_list`. Any
+    // other key ends the cycle.
+    completion_cycle: Option<(Vec<Completion>, usize)>,
+    docked_completions: bool,
+    history_search: Option<HistorySearch>,
+    reverse_search: Option<ReverseSearch>,
+    show_help: bool,
+    transcript_mode: bool,
+    alternate_screen: bool,
+    mouse_capture: bool,
+    click_select: bool,
+    last_click: Option<Click>,
+    copy_handler: Option<CopyHandler>,
+    error_panel: bool,
+    error_selection: usize,
+    // Header id of the fold picked with `Action::SelectFold`, while picking.
+    fold_selection: Option<u64>,
+    stats: Arc<LogStats>,
+    restore_input_on_error: bool,
+    context_lines: usize,
+    compact: bool,
+    word_wrap: bool,
+    recency_fade: bool,
+    newest_first: bool,
+    level_badges: bool,
+    tab_width: usize,
+    auto_link: bool,
+    preserve_colors: bool,
+    timestamps: bool,
+    timestamp_format: String,
+    // Lines with a level below this are hidden; see `set_min_level`.
+    min_level: Option<Level>,
+    show_count: bool,
+    activity_spinner: bool,
+    unbound_key_hints: bool,
+    // Shown in the input box title until KEY_HINT_TIME after it was set.
+    key_hint: Option<(String, Instant)>,
+    exit: ExitConfig,
+    // When a quit key was pressed, while it waits for confirmation.
+    exit_armed: Option<Instant>,
+    #[cfg(feature = "image-banner")]
+    splash: Option<Splash>,
+    render_cache: RefCell<Option<RenderCache>>,
+    scroll_anchor: std::cell::Cell<Option<ScrollAnchor>>,
+    // Inside of the message pane's border as of the last frame, for mapping
+    // clicks back to rows.
+    message_pane: std::cell::Cell<Rect>,
+    max_fps: Option<u32>,
+    last_frame: Option<Instant>,
+    auto_focus_errors: bool,
+    seen_error: u64,
+    focused_error: Option<u64>,
+}
+
+impl Default for TerminalUI {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TerminalUI {
@@ -97,10 +519,98 @@ impl TerminalUI {
             messages: Arc::new(Mutex::new(VecDeque::with_capacity(MAX_MESSAGES))),
             input: String::new(),
             cursor_position: 0,
+            kill_buffer: String::new(),
             prompt: String::from("> "),
-            scroll_offset: 0,
+            prompt_style: Style::default().fg(Color::Cyan).add_modifier(Modifier::BOLD),
+            prompt_separator: String::new(),
+            prompt_separator_style: Style::default(),
+            input_editor: None,
+            input_style: Style::default(),
+            view: Arc::new(ViewState::default()),
+            trim: Arc::new(Mutex::new(TrimState::default())),
+            subscribers: broadcast::channel(SUBSCRIBER_CAPACITY).0,
+            log_file: Arc::new(Mutex::new(None)),
+            stderr_mirror: Arc::new(AtomicBool::new(false)),
+            stderr_writer: Arc::new(OnceLock::new()),
+            incoming_timestamp: Arc::new(Mutex::new(None)),
+            placeholder: Arc::new(Mutex::new(String::new())),
+            labels: Arc::new(Mutex::new(HashMap::new())),
+            folds: Arc::new(Mutex::new(FoldState::default())),
+            prompt_once_requests: Arc::new(Mutex::new(Vec::new())),
+            prompt_once: None,
             history: Vec::new(),
             history_index: 0,
+            history_file: None,
+            annotated_history: None,
+            unhandled_key_handler: None,
+            key_observer: None,
+            heartbeat: None,
+            input_transform: None,
+            function_keys: BTreeMap::from([
+                (1, Action::ShowHelp),
+                (2, Action::CycleLevelFilter),
+                (4, Action::ToggleErrorPanel),
+                (5, Action::ClearMessages),
+                (6, Action::ToggleFolds),
+                (7, Action::SelectFold),
+            ]),
+            edit_keys: vec![
+                ((KeyCode::Char('w'), KeyModifiers::CONTROL), EditAction::CutWordBefore),
+                ((KeyCode::Char('k'), KeyModifiers::CONTROL), EditAction::CutToLineEnd),
+                ((KeyCode::Char('u'), KeyModifiers::CONTROL), EditAction::CutToLineStart),
+                ((KeyCode::Char('y'), KeyModifiers::CONTROL), EditAction::Paste),
+                ((KeyCode::Char('r'), KeyModifiers::CONTROL), EditAction::ReverseSearch),
+            ],
+            history_search_key: (KeyCode::Char('t'), KeyModifiers::CONTROL),
+            history_search: None,
+            reverse_search: None,
+            completion_behavior: CompletionBehavior::default(),
+            empty_submit: EmptySubmit::default(),
+            empty_tab: EmptyTab::default(),
+            completion_list: Vec::new(),
+            completion_cycle: None,
+            completion_selection: 0,
+            docked_completions: false,
+            show_help: false,
+            transcript_mode: false,
+            alternate_screen: std::env::var_os(NO_ALT_SCREEN_ENV).is_none(),
+            mouse_capture: true,
+            click_select: false,
+            last_click: None,
+            copy_handler: None,
+            error_panel: false,
+            error_selection: 0,
+            fold_selection: None,
+            stats: Arc::new(LogStats::default()),
+            restore_input_on_error: false,
+            context_lines: 0,
+            compact: false,
+            word_wrap: false,
+            recency_fade: false,
+            newest_first: false,
+            level_badges: false,
+            tab_width: DEFAULT_TAB_WIDTH,
+            auto_link: false,
+            preserve_colors: false,
+            timestamps: false,
+            timestamp_format: DEFAULT_TIMESTAMP_FORMAT.to_string(),
+            min_level: None,
+            show_count: false,
+            activity_spinner: false,
+            unbound_key_hints: false,
+            key_hint: None,
+            exit: ExitConfig::default(),
+            exit_armed: None,
+            #[cfg(feature = "image-banner")]
+            splash: None,
+            render_cache: RefCell::new(None),
+            scroll_anchor: std::cell::Cell::new(None),
+            message_pane: std::cell::Cell::new(Rect::default()),
+            max_fps: Some(DEFAULT_MAX_FPS),
+            last_frame: None,
+            auto_focus_errors: false,
+            seen_error: 0,
+            focused_error: None,
         }
     }
 
@@ -108,282 +618,4575 @@ impl TerminalUI {
         self.prompt = prompt;
     }
 
-    pub fn get_message_logger(&self) -> MessageLogger {
-        MessageLogger {
-            messages: Arc::clone(&self.messages),
-        }
+    pub fn set_prompt_style(&mut self, style: Style) {
+        self.prompt_style = style;
     }
 
-    pub async fn run<FInput, Fut, FTab>(
-        &mut self,
-        mut on_command: FInput,
-        mut on_autocomplete: FTab
-    ) -> io::Result<()>
+    /// Drawn in `style` between the prompt and the input, e.g. " › ". It is
+    /// display only: transcript echoes use the bare prompt. Empty by
+    /// default.
+    pub fn set_prompt_separator(&mut self, separator: &str, style: Style) {
+        self.prompt_separator = separator.to_string();
+        self.prompt_separator_style = style;
+    }
+
+    /// Style of the typed text; unset fields fall back to the input box's
+    /// green.
+    pub fn set_input_style(&mut self, style: Style) {
+        self.input_style = style;
+    }
+
+    /// Turns the input box into a small editor: Alt+Enter (or Shift+Enter,
+    /// where the terminal reports it) starts a new line, Up/Down move
+    /// between lines before reaching history, and lines are numbered with
+    /// the cursor's line and column in the title. The box grows to
+    /// `max_rows` lines of text, then scrolls. Enter still submits the
+    /// whole text. Not used in compact or transcript mode. None, the
+    /// default, is the single-line input.
+    pub fn set_input_editor(&mut self, max_rows: Option<u16>) {
+        self.input_editor = max_rows.map(|rows| rows.max(1));
+    }
+
+    /// Called for keys the built-in bindings don't consume. The handler
+    /// returns `true` if it handled the key. The editing keys (Ctrl+W, K,
+    /// U, Y and R) are built-in bindings too; free them with
+    /// `unbind_edit_key` to get them here.
+    pub fn set_unhandled_key_handler<F>(&mut self, handler: F)
     where
-        FInput: FnMut(String) -> Fut,
-        Fut: std::future::Future<Output = Result<bool, String>>,
-        FTab: FnMut(&str, usize) -> Vec<String>,
+        F: FnMut(KeyEvent) -> bool + 'static,
     {
-        enable_raw_mode()?;
-        let mut stdout = io::stdout();
-        execute!(stdout, EnterAlternateScreen)?;
-        let backend = CrosstermBackend::new(stdout);
-        let mut terminal = Terminal::new(backend)?;
-
-        // Ensure cleanup happens even on panic
-        let cleanup = Cleanup;
-        let result = self.run_loop(&mut terminal, &mut on_command, &mut on_autocomplete).await;
-        drop(cleanup);
+        self.unhandled_key_handler = Some(Box::new(handler));
+    }
 
-        disable_raw_mode()?;
-        execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
-        terminal.show_cursor()?;
+    /// Sees every key press before it is handled, e.g. for telemetry or
+    /// idle detection. It can't consume keys; see
+    /// `set_unhandled_key_handler` for that.
+    pub fn set_key_observer<F>(&mut self, observer: F)
+    where
+        F: FnMut(&KeyEvent) + 'static,
+    {
+        self.key_observer = Some(Box::new(observer));
+    }
 
-        result
+    /// Calls `callback` every `interval` while the UI runs, whatever the
+    /// user is doing, e.g. to send keepalives. It runs on the UI thread
+    /// between frames, so it should return quickly.
+    pub fn set_heartbeat<F>(&mut self, interval: Duration, callback: F)
+    where
+        F: FnMut() + 'static,
+    {
+        self.heartbeat = Some(Heartbeat { interval, last: Instant::now(), callback: Box::new(callback) });
     }
 
-    async fn run_loop<FInput, Fut, FTab>(
-        &mut self,
-        terminal: &mut Terminal<CrosstermBackend<io::Stdout>>,
-        on_command: &mut FInput,
-        on_autocomplete: &mut FTab
-    ) -> io::Result<()>
+    /// Rewrites each submitted line before it reaches the command handler,
+    /// e.g. to expand aliases. History keeps the typed line unless the
+    /// transform says otherwise.
+    pub fn set_input_transform<F>(&mut self, transform: F)
     where
-        FInput: FnMut(String) -> Fut,
-        Fut: std::future::Future<Output = Result<bool, String>>,
-        FTab: FnMut(&str, usize) -> Vec<String>,
+        F: FnMut(&str) -> TransformedInput + 'static,
     {
-        loop {
-            terminal.draw(|f| self.draw(f))?;
+        self.input_transform = Some(Box::new(transform));
+    }
 
-            if event::poll(std::time::Duration::from_millis(50))? {
-                if let Event::Key(key) = event::read()? {
-                    match self.handle_key(key, on_command, on_autocomplete).await {
-                        KeyAction::Exit => return Ok(()),
-                        KeyAction::Continue => {}
-                    }
-                }
-            }
-        }
+    /// Echo each submitted line into the scrollback and use a borderless
+    /// one-line input, like a classic REPL transcript.
+    pub fn set_transcript_mode(&mut self, enabled: bool) {
+        self.transcript_mode = enabled;
     }
 
-    async fn handle_key<FInput, Fut, FTab>(
-        &mut self,
-        key: KeyEvent,
-        on_command: &mut FInput,
-        on_autocomplete: &mut FTab
-    ) -> KeyAction
+    /// Draw on the alternate screen (the default). Turning it off leaves
+    /// every frame and any panic output in the normal scrollback after exit,
+    /// which helps when debugging an embedding; it is not meant for
+    /// production use. Setting `RIEGE_XTERM_NO_ALT_SCREEN` has the same
+    /// effect.
+    pub fn set_alternate_screen(&mut self, enabled: bool) {
+        self.alternate_screen = enabled;
+    }
+
+    /// Take mouse events from the terminal (the default), so the wheel
+    /// scrolls the message pane. Only applies while the alternate screen is
+    /// on. While it's on, most terminals only select text with Shift held;
+    /// turn it off to keep plain selection. Takes effect on the next `run`.
+    pub fn set_mouse_capture(&mut self, enabled: bool) {
+        self.mouse_capture = enabled;
+    }
+
+    /// Copy the word under the pointer on a double click in the message
+    /// pane, and the whole line on a triple click. Needs mouse capture.
+    pub fn set_click_select(&mut self, enabled: bool) {
+        self.click_select = enabled;
+    }
+
+    /// Receives text copied by clicking instead of the terminal clipboard,
+    /// which is otherwise set with an OSC 52 escape.
+    pub fn set_copy_handler<F>(&mut self, handler: F)
     where
-        FInput: FnMut(String) -> Fut,
-        Fut: std::future::Future<Output = Result<bool, String>>,
-        FTab: FnMut(&str, usize) -> Vec<String>,
+        F: FnMut(&str) + 'static,
     {
-        match key.code {
-            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                KeyAction::Exit
-            }
-            KeyCode::Enter => {
-                let cmd = self.input.clone();
+        self.copy_handler = Some(Box::new(handler));
+    }
 
-                if !cmd.trim().is_empty() {
-                    self.history.push(cmd.clone());
-                }
-                self.history_index = self.history.len();
+    /// Shows a side panel listing error and warning lines. While it is open,
+    /// Up/Down select an entry, Enter scrolls to it and Esc closes the panel.
+    pub fn set_error_panel(&mut self, visible: bool) {
+        self.error_panel = visible;
+        self.error_selection = 0;
+    }
 
-                self.input.clear();
-                self.cursor_position = 0;
-                self.scroll_offset = 0;
+    /// While scrolled up, keep the newest `lines` messages pinned above the
+    /// input so fresh output stays visible. 0 disables it.
+    pub fn set_context_lines(&mut self, lines: usize) {
+        self.context_lines = lines;
+    }
 
-                match on_command(cmd).await {
-                    Ok(true) => KeyAction::Exit,
-                    _ => KeyAction::Continue,
-                }
-            }
-            KeyCode::Up => {
-                if self.history_index > 0 {
-                    self.history_index -= 1;
-                    self.input = self.history[self.history_index].clone();
-                    self.cursor_position = self.input.len();
-                }
-                KeyAction::Continue
-            }
-            KeyCode::Down => {
-                if self.history_index < self.history.len() {
-                    self.history_index += 1;
-                    if self.history_index < self.history.len() {
-                        self.input = self.history[self.history_index].clone();
-                    } else {
-                        self.input.clear();
-                    }
-                    self.cursor_position = self.input.len();
-                }
-                KeyAction::Continue
-            }
-            KeyCode::Char(c) => {
-                self.input.insert(self.cursor_position, c);
-                self.cursor_position += 1;
-                KeyAction::Continue
-            }
-            KeyCode::Backspace => {
-                if self.cursor_position > 0 {
-                    self.input.remove(self.cursor_position - 1);
-                    self.cursor_position -= 1;
-                }
-                KeyAction::Continue
-            }
-            KeyCode::Left => {
-                if self.cursor_position > 0 { self.cursor_position -= 1; }
-                KeyAction::Continue
-            }
-            KeyCode::Right => {
-                if self.cursor_position < self.input.len() { self.cursor_position += 1; }
-                KeyAction::Continue
-            }
-            KeyCode::Tab => {
-                let suggestions = on_autocomplete(&self.input, self.cursor_position);
-                if !suggestions.is_empty() {
-                    self.input = suggestions[0].clone();
-                    self.cursor_position = self.input.len();
-                }
-                KeyAction::Continue
-            }
-            KeyCode::PageUp => {
-                self.scroll_offset = self.scroll_offset.saturating_add(5);
-                KeyAction::Continue
-            }
-            KeyCode::PageDown => {
-                self.scroll_offset = self.scroll_offset.saturating_sub(5);
-                KeyAction::Continue
-            }
-            KeyCode::Home => {
-                self.cursor_position = 0;
-                KeyAction::Continue
-            }
-            KeyCode::End => {
-                self.cursor_position = self.input.len();
-                KeyAction::Continue
-            }
-            _ => KeyAction::Continue,
+    /// Drop all borders and titles and use a one-line input, for embedding
+    /// in a small area.
+    pub fn set_compact(&mut self, enabled: bool) {
+        self.compact = enabled;
+    }
+
+    /// Put a submitted line back into the input when the command handler
+    /// returns an error, so it can be fixed and resubmitted.
+    pub fn set_restore_input_on_error(&mut self, enabled: bool) {
+        self.restore_input_on_error = enabled;
+    }
+
+    /// Wrap long messages onto several rows instead of cutting them off at
+    /// the pane edge. Wrapping is measured in terminal cells.
+    pub fn set_word_wrap(&mut self, enabled: bool) {
+        self.word_wrap = enabled;
+    }
+
+    /// Draw older visible lines progressively dimmer toward the top of the
+    /// pane. Only the on-screen colors change.
+    pub fn set_recency_fade(&mut self, enabled: bool) {
+        self.recency_fade = enabled;
+    }
+
+    /// Columns between tab stops when drawing tabs in messages, counted
+    /// from the start of the message text. At least 1.
+    pub fn set_tab_width(&mut self, width: usize) {
+        self.tab_width = width.max(1);
+    }
+
+    /// Start each line with a short colored badge for its level, e.g. `[!]`
+    /// for warnings, in place of the level prefix, and draw the rest of the
+    /// line uncolored. Lines without a level are indented to match.
+    pub fn set_level_badges(&mut self, enabled: bool) {
+        self.level_badges = enabled;
+    }
+
+    /// Show the newest message on the top row with older ones below it.
+    /// The scroll offset still counts messages from the newest, so PageDown
+    /// moves into history in this mode.
+    pub fn set_newest_first(&mut self, enabled: bool) {
+        self.newest_first = enabled;
+    }
+
+    /// When an error is logged while the view is at the tail and the input
+    /// is empty, keep that error on screen as more output arrives. Any key
+    /// press hands scrolling back to the user.
+    pub fn set_auto_focus_errors(&mut self, enabled: bool) {
+        self.auto_focus_errors = enabled;
+        self.seen_error = self.stats.last_error.load(Ordering::Relaxed);
+        self.focused_error = None;
+    }
+
+    /// See `MessageLogger::set_strip_incoming_timestamp`.
+    pub fn set_strip_incoming_timestamp(&mut self, format: Option<&str>) {
+        self.get_message_logger().set_strip_incoming_timestamp(format);
+    }
+
+    /// Dimmed hint shown after the prompt while the input is empty. An
+    /// empty string removes it.
+    pub fn set_placeholder(&mut self, text: &str) {
+        self.get_message_logger().set_placeholder(text);
+    }
+
+    pub fn set_log_file(&mut self, path: &Path, max_size: u64) -> io::Result<()> {
+        self.get_message_logger().set_log_file(path, max_size)
+    }
+
+    /// See `MessageLogger::set_stderr_mirror`.
+    pub fn set_stderr_mirror(&mut self, enabled: bool) -> bool {
+        self.get_message_logger().set_stderr_mirror(enabled)
+    }
+
+    /// Loads command history from `path` (a missing file is fine) and saves
+    /// it back there when `run` returns, however the session ended. The
+    /// annotated record is saved with it, and loaded too if annotation is
+    /// already on.
+    pub fn set_history_file(&mut self, path: &Path) -> io::Result<()> {
+        let saved = history_file::load(path)?;
+        self.history = saved.commands;
+        if let Some(record) = self.annotated_history.as_mut() {
+            record.splice(0..0, saved.annotated);
+            trim_history_record(record);
         }
+        self.history_index = self.history.len();
+        self.history_file = Some(path.to_path_buf());
+        Ok(())
     }
 
-    fn draw(&self, f: &mut Frame) {
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints([
-                Constraint::Min(3),
-                Constraint::Length(3),
-            ])
-            .split(f.area());
+    pub fn set_trim_strategy(&mut self, strategy: TrimStrategy) {
+        self.get_message_logger().set_trim_strategy(strategy);
+    }
 
-        let messages = self.messages.lock().unwrap();
+    pub fn bind_function_key(&mut self, n: u8, action: Action) {
+        self.function_keys.insert(n, action);
+    }
 
-        let available_height = chunks[0].height.saturating_sub(2) as usize;
-        let total_messages = messages.len();
+    /// Unbound function keys are passed to the unhandled-key handler.
+    pub fn unbind_function_key(&mut self, n: u8) {
+        self.function_keys.remove(&n);
+    }
 
-        let max_scroll = if total_messages > available_height {
-            total_messages - available_height
-        } else {
-            0
-        };
+    /// Moves a line editing command to another key, or adds a second key
+    /// for it. Whatever `code` with `modifiers` did before is replaced.
+    pub fn bind_edit_key(&mut self, code: KeyCode, modifiers: KeyModifiers, action: EditAction) {
+        self.unbind_edit_key(code, modifiers);
+        self.edit_keys.push(((code, modifiers), action));
+    }
 
-        let clamped_scroll = self.scroll_offset.min(max_scroll);
+    /// Unbound editing keys are passed to the unhandled-key handler.
+    pub fn unbind_edit_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        self.edit_keys.retain(|&(key, _)| key != (code, modifiers));
+    }
 
-        let start_index = if total_messages > available_height {
-            total_messages - available_height - clamped_scroll
-        } else {
-            0
-        };
+    pub fn set_completion_behavior(&mut self, behavior: CompletionBehavior) {
+        self.completion_behavior = behavior;
+    }
 
-        let items: Vec<ListItem> = messages
-            .iter()
-            .skip(start_index)
-            .take(available_height)
-            .map(|m| {
-                let cleaned = strip_ansi_codes(m);
-                let (text, color) = parse_message_type(&cleaned);
-                ListItem::new(Line::from(Span::styled(text, Style::default().fg(color))))
-            })
-            .collect();
+    /// Show the number of stored messages against the scrollback cap, as
+    /// "N/MAX", in the message pane title.
+    pub fn set_show_count(&mut self, enabled: bool) {
+        self.show_count = enabled;
+    }
 
-        let title = if clamped_scroll > 0 {
-            format!("R-Term (↑{})", clamped_scroll)
-        } else {
-            "R-Term".to_string()
-        };
+    /// Animate a spinner in the message pane title while lines are being
+    /// logged, i.e. until half a second after the newest one.
+    pub fn set_activity_spinner(&mut self, enabled: bool) {
+        self.activity_spinner = enabled;
+    }
 
-        let messages_list = List::new(items)
-            .block(Block::default()
-                .borders(Borders::ALL)
-                .title(title)
-                .style(Style::default().fg(Color::Cyan)));
+    /// Show `image` above the message pane, in place of a text banner,
+    /// until the first key press. See `core::image_banner`.
+    #[cfg(feature = "image-banner")]
+    pub fn set_banner_image(&mut self, image: BannerImage) {
+        self.splash = Some(Splash { image, area: std::cell::Cell::new(None), drawn: None, ended: false });
+    }
 
-        f.render_widget(messages_list, chunks[0]);
+    /// Most frames drawn per second, 30 by default; `None` draws after
+    /// every batch of events. Frames skipped under the cap are drawn as soon
+    /// as the interval is up, so heavy logging or fast typing costs at most
+    /// this many redraws.
+    pub fn set_max_fps(&mut self, fps: Option<u32>) {
+        self.max_fps = fps.filter(|&fps| fps > 0);
+    }
 
-        let input_text = format!("{}{}", self.prompt, self.input);
-        let input = Paragraph::new(input_text)
-            .block(Block::default()
-                .borders(Borders::ALL)
-                .title("Input")
-                .style(Style::default().fg(Color::Green)));
+    /// Briefly show "unbound key: <name>" in the input box title when a key
+    /// does nothing, neither a built-in binding nor the unhandled key
+    /// handler taking it.
+    pub fn set_unbound_key_hints(&mut self, enabled: bool) {
+        self.unbound_key_hints = enabled;
+    }
 
-        f.render_widget(input, chunks[1]);
+    /// Draw text in the colors and attributes its ANSI SGR codes set,
+    /// instead of dropping the codes. Text after a reset code goes back to
+    /// the line's usual color.
+    pub fn set_preserve_colors(&mut self, enabled: bool) {
+        self.preserve_colors = enabled;
+    }
 
-        let prompt_display_width = self.prompt.len() as u16;
-        let cursor_x = chunks[1].x + prompt_display_width + self.cursor_position as u16 + 1;
-        let cursor_y = chunks[1].y + 1;
-        f.set_cursor_position((cursor_x, cursor_y));
+    /// Hide lines whose level is below `level` (debug, info, success,
+    /// warning, error, in that order), as if they weren't there; lines
+    /// without a level always show. None, the default, shows everything.
+    /// F2 steps through info, warning, error and off.
+    pub fn set_min_level(&mut self, level: Option<Level>) {
+        self.min_level = level;
+        // Lines come and go, so the scroll anchor has to look again.
+        self.view.revision.fetch_add(1, Ordering::Relaxed);
     }
-}
 
-enum KeyAction {
-    Continue,
-    Exit,
-}
+    /// Show the local time each line was logged, as `HH:MM:SS` unless
+    /// `set_timestamp_format` says otherwise, dimmed in front of it. Lines
+    /// of a multi-line message each have their own. Only drawn: the stored
+    /// text, log file and stderr mirror are unchanged.
+    pub fn set_timestamps(&mut self, enabled: bool) {
+        self.timestamps = enabled;
+    }
 
-#[derive(Clone)]
-pub struct MessageLogger {
-    pub messages: Arc<Mutex<VecDeque<String>>>,
-}
+    /// How `set_timestamps` writes the time, e.g. `"%Y-%m-%d %H:%M:%S.%f"`:
+    /// `%Y` year, `%m` month, `%d` day, `%H` hour (24h), `%I` hour (12h),
+    /// `%p` AM/PM, `%M` minute, `%S` second, `%f` milliseconds, `%%` a
+    /// percent sign, and anything else as is. Fails with `InvalidInput`,
+    /// keeping the old format, if another `%` field is used.
+    pub fn set_timestamp_format(&mut self, format: &str) -> io::Result<()> {
+        format_timestamp(format, &DateTime::default()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        self.timestamp_format = format.to_string();
+        self.view.revision.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
 
-impl MessageLogger {
-    pub fn log(&self, message: String) {
-        let mut msgs = self.messages.lock().unwrap();
+    /// Underline `http://` and `https://` URLs in messages and show them in
+    /// blue.
+    pub fn set_auto_link(&mut self, enabled: bool) {
+        self.auto_link = enabled;
+    }
 
-        // Split multi-line messages into separate entries
-        for line in message.lines() {
-            if msgs.len() >= MAX_MESSAGES {
-                msgs.pop_front();
-            }
-            msgs.push_back(line.to_string());
-        }
+    /// Show listed completions in a panel below the input instead of over
+    /// the bottom of the message pane.
+    pub fn set_docked_completions(&mut self, enabled: bool) {
+        self.docked_completions = enabled;
+    }
 
-        // Handle empty messages (like blank lines)
-        if message.is_empty() || message == "\n" {
-            if msgs.len() >= MAX_MESSAGES {
-                msgs.pop_front();
-            }
-            msgs.push_back(String::new());
-        }
+    /// What Enter does when the input is empty or only whitespace.
+    pub fn set_empty_submit_behavior(&mut self, behavior: EmptySubmit) {
+        self.empty_submit = behavior;
     }
 
-    pub fn info(&self, message: &str) {
-        self.log(format!("[INFO] {}", message));
+    /// Which keys end the session, and whether they need confirming.
+    pub fn set_exit_config(&mut self, config: ExitConfig) {
+        self.exit = config;
     }
 
-    pub fn error(&self, message: &str) {
-        self.log(format!("[ERROR] {}", message));
+    /// What Tab does when the input is empty.
+    pub fn set_empty_tab_behavior(&mut self, behavior: EmptyTab) {
+        self.empty_tab = behavior;
     }
 
-    pub fn success(&self, message: &str) {
-        self.log(format!("[SUCCESS] {}", message));
+    /// Also record each submitted command with its submission time and
+    /// whether the command handler succeeded, readable through
+    /// `annotated_history`. The last `HISTORY_FILE_CAPACITY` are kept. Off
+    /// by default; disabling it drops the record.
+    pub fn set_annotated_history(&mut self, enabled: bool) {
+        match (enabled, self.annotated_history.is_some()) {
+            (true, false) => self.annotated_history = Some(Vec::new()),
+            (false, true) => self.annotated_history = None,
+            _ => {}
+        }
+    }
+
+    /// Commands submitted since annotation was enabled, oldest first.
+    pub fn annotated_history(&self) -> &[HistoryEntry] {
+        self.annotated_history.as_deref().unwrap_or_default()
     }
 
-    pub fn warning(&self, message: &str) {
-        self.log(format!("[WARNING] {}", message));
+    /// Key that opens the fuzzy history search, Ctrl+T by default.
+    pub fn set_history_search_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        self.history_search_key = (code, modifiers);
     }
 
-    pub fn debug(&self, message: &str) {
-        self.log(format!("[DEBUG] {}", message));
+    pub fn get_message_logger(&self) -> MessageLogger {
+        MessageLogger {
+            messages: Arc::clone(&self.messages),
+            view: Arc::clone(&self.view),
+            trim: Arc::clone(&self.trim),
+            subscribers: self.subscribers.clone(),
+            log_file: Arc::clone(&self.log_file),
+            stderr_mirror: Arc::clone(&self.stderr_mirror),
+            stderr_writer: Arc::clone(&self.stderr_writer),
+            incoming_timestamp: Arc::clone(&self.incoming_timestamp),
+            placeholder: Arc::clone(&self.placeholder),
+            labels: Arc::clone(&self.labels),
+            folds: Arc::clone(&self.folds),
+            prompt_once_requests: Arc::clone(&self.prompt_once_requests),
+            stats: Arc::clone(&self.stats),
+        }
     }
-}
\ No newline at end of file
+
+    // The one-shot prompt while a question is open, else the usual one.
+    fn current_prompt(&self) -> Cow<'_, str> {
+        if let Some(search) = &self.reverse_search {
+            let failed = if search.found.is_none() && !search.query.is_empty() { "failed " } else { "" };
+            return Cow::Owned(format!("({}reverse-i-search)`{}': ", failed, search.query));
+        }
+        Cow::Borrowed(self.prompt_once.as_ref().map_or(&self.prompt, |question| &question.prompt))
+    }
+
+    fn scroll_offset(&self) -> usize {
+        self.view.scroll_offset.load(Ordering::Relaxed)
+    }
+
+    fn set_scroll_offset(&self, offset: usize) {
+        self.view.scroll_offset.store(offset, Ordering::Relaxed);
+    }
+
+    pub async fn run<FInput, Fut, FTab>(
+        &mut self,
+        on_command: FInput,
+        on_autocomplete: FTab
+    ) -> io::Result<()>
+    where
+        FInput: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<bool, String>>,
+        FTab: FnMut(&str, usize) -> Vec<Completion>,
+    {
+        self.run_with(&mut Callbacks { on_command, on_autocomplete }).await
+    }
+
+    /// Like `run`, with submitted lines and Tab going to `handler`.
+    pub async fn run_with<H: CommandHandler>(&mut self, handler: &mut H) -> io::Result<()> {
+        // Capturing the mouse outside the alternate screen would take
+        // selection and scrollback away from the shell's own output.
+        let mouse_capture = self.mouse_capture && self.alternate_screen;
+        enable_raw_mode()?;
+        // Ensure cleanup happens even on panic, or if a step below fails
+        let cleanup = Cleanup { alternate_screen: self.alternate_screen, mouse_capture };
+        let mut stdout = io::stdout();
+        if self.alternate_screen {
+            execute!(stdout, EnterAlternateScreen)?;
+        }
+        if mouse_capture {
+            execute!(stdout, EnableMouseCapture)?;
+        }
+        let backend = CrosstermBackend::new(stdout);
+        let mut terminal = Terminal::new(backend)?;
+
+        let result = self.run_on(&mut terminal, &mut CrosstermEvents, handler).await;
+        drop(cleanup);
+
+        // Saved before anything below can return early, and after the
+        // terminal is restored so a slow disk doesn't leave it in raw mode.
+        // Losing the history isn't worth failing the session over.
+        if let Some(path) = &self.history_file {
+            let annotated = self.annotated_history.as_deref().unwrap_or_default();
+            if let Err(e) = history_file::save(path, &self.history, annotated, HISTORY_SAVE_TIMEOUT) {
+                self.get_message_logger().warning(&format!("Couldn't save history to {}: {}", path.display(), e));
+            }
+        }
+
+        disable_raw_mode()?;
+        if mouse_capture {
+            execute!(terminal.backend_mut(), DisableMouseCapture)?;
+        }
+        if self.alternate_screen {
+            execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+        }
+        terminal.show_cursor()?;
+
+        result
+    }
+
+    /// The event loop on its own: draws to `terminal` and handles events
+    /// from `events` until the session ends, without touching raw mode or
+    /// the alternate screen. `run_with` calls it with the real terminal and
+    /// `CrosstermEvents`; tests can pass a `TestBackend` and scripted events.
+    pub async fn run_on<B, E, H>(&mut self, terminal: &mut Terminal<B>, events: &mut E, handler: &mut H) -> io::Result<()>
+    where
+        B: Backend,
+        E: EventSource,
+        H: CommandHandler,
+    {
+        let result = self.event_loop(terminal, events, handler).await;
+        // Whatever ended the session, an open question gets its answer.
+        self.take_prompt_once();
+        self.cancel_prompt_once();
+        result
+    }
+
+    async fn event_loop<B, E, H>(&mut self, terminal: &mut Terminal<B>, events: &mut E, handler: &mut H) -> io::Result<()>
+    where
+        B: Backend,
+        E: EventSource,
+        H: CommandHandler,
+    {
+        loop {
+            self.beat();
+            self.take_prompt_once();
+            self.sync_cursor();
+            self.follow_error();
+            let wait = self.until_next_frame();
+            if wait.is_zero() {
+                terminal.draw(|f| self.draw(f))?;
+                self.last_frame = Some(Instant::now());
+                #[cfg(feature = "image-banner")]
+                self.paint_splash()?;
+            }
+
+            let timeout = if wait.is_zero() { IDLE_TICK } else { wait.min(IDLE_TICK) };
+            if !events.poll(timeout)? {
+                continue;
+            }
+
+            // Handle everything already queued before redrawing, so a paste
+            // on a terminal without bracketed paste, which arrives as a burst
+            // of key events, costs one frame rather than one per character.
+            // Keys keep their usual meaning, so Enter in the burst submits.
+            for _ in 0..MAX_EVENTS_PER_FRAME {
+                match events.read()? {
+                    Event::Key(key) => match self.handle_key(key, handler).await {
+                        KeyAction::Exit => return Ok(()),
+                        KeyAction::Continue => {}
+                    },
+                    Event::Mouse(mouse) => self.handle_mouse(mouse)?,
+                    _ => {}
+                }
+                if !events.poll(Duration::ZERO)? {
+                    break;
+                }
+            }
+        }
+    }
+
+    // Draws the banner image into the room the frame left for it, or
+    // removes it once it's over. Written straight to stdout: the image
+    // escapes are opaque to ratatui, which skips the cells underneath.
+    #[cfg(feature = "image-banner")]
+    fn paint_splash(&mut self) -> io::Result<()> {
+        use crossterm::{cursor::MoveTo, queue, style::Print};
+        use std::io::Write;
+
+        let Some(splash) = self.splash.as_mut() else {
+            return Ok(());
+        };
+        let mut stdout = io::stdout();
+        if splash.ended {
+            if let Some(clear) = splash.image.clear_escape() {
+                queue!(stdout, Print(clear))?;
+            }
+            self.splash = None;
+        } else if let Some(area) = splash.area.get().filter(|&area| Some(area) != splash.drawn) {
+            queue!(stdout, MoveTo(area.x, area.y), Print(splash.image.escape(area.width, area.height)))?;
+            splash.drawn = Some(area);
+        }
+        stdout.flush()
+    }
+
+    #[cfg(feature = "image-banner")]
+    fn splash_height(&self) -> u16 {
+        match &self.splash {
+            Some(splash) if !splash.ended => SPLASH_ROWS,
+            _ => 0,
+        }
+    }
+
+    #[cfg(not(feature = "image-banner"))]
+    fn splash_height(&self) -> u16 {
+        0
+    }
+
+    // Zero if a frame may be drawn now, else how long until one may.
+    fn until_next_frame(&self) -> Duration {
+        match (self.max_fps, self.last_frame) {
+            (Some(fps), Some(last)) => (Duration::from_secs(1) / fps).saturating_sub(last.elapsed()),
+            _ => Duration::ZERO,
+        }
+    }
+
+    fn beat(&mut self) {
+        if let Some(heartbeat) = self.heartbeat.as_mut() {
+            if heartbeat.last.elapsed() >= heartbeat.interval {
+                heartbeat.last = Instant::now();
+                (heartbeat.callback)();
+            }
+        }
+    }
+
+    // Opens the newest question asked through a MessageLogger handle,
+    // cancelling the ones it replaces. Answers are called without the lock
+    // held, since they may ask again.
+    fn take_prompt_once(&mut self) {
+        let requests = std::mem::take(&mut *locks::lock(&self.prompt_once_requests, Rank::Leaf).unwrap());
+        for question in requests {
+            self.cancel_prompt_once();
+            self.prompt_once = Some(question);
+        }
+    }
+
+    fn cancel_prompt_once(&mut self) {
+        if let Some(question) = self.prompt_once.take() {
+            (question.answer)(None);
+        }
+    }
+
+    fn answer_prompt_once(&mut self) {
+        let Some(question) = self.prompt_once.take() else {
+            return;
+        };
+        let line = std::mem::take(&mut self.input);
+        self.cursor_position = 0;
+        if self.transcript_mode {
+            self.get_message_logger().log(format!("{}{}", question.prompt, line));
+        }
+        if question.remember && !line.trim().is_empty() {
+            self.history.push(line.clone());
+        }
+        self.history_index = self.history.len();
+        (question.answer)(Some(line));
+    }
+
+    // Applies a cursor move requested through a MessageLogger handle, then
+    // publishes the current cursor and input length for it to read.
+    fn sync_cursor(&mut self) {
+        let input_len = self.input.chars().count();
+        let request = self.view.cursor_request.swap(NO_CURSOR_REQUEST, Ordering::Relaxed);
+        if request != NO_CURSOR_REQUEST {
+            self.cursor_position = grapheme_at_char(&self.input, request);
+        }
+        self.view.cursor.store(self.cursor_chars(), Ordering::Relaxed);
+        self.view.input_len.store(input_len, Ordering::Relaxed);
+    }
+
+    // The cursor as a char index, as handlers and the logger count it.
+    fn cursor_chars(&self) -> usize {
+        self.input[..self.cursor_position].chars().count()
+    }
+
+    fn follow_error(&mut self) {
+        if !self.auto_focus_errors {
+            return;
+        }
+
+        let last_error = self.stats.last_error.load(Ordering::Relaxed);
+        if last_error != self.seen_error {
+            self.seen_error = last_error;
+            if self.scroll_offset() == 0 && self.input.is_empty() {
+                self.focused_error = Some(last_error);
+            }
+        }
+
+        if let Some(error) = self.focused_error {
+            // Entries shown after the error, i.e. its distance from the tail.
+            let messages = locks::lock(&self.messages, Rank::Messages).unwrap();
+            let after = messages.iter().position(|e| e.id == error).and_then(|index| self.offset_of(&messages, index));
+            drop(messages);
+            let Some(after) = after else {
+                // Evicted: nothing left to keep on screen.
+                self.focused_error = None;
+                return;
+            };
+            let height = self.view.page_height.load(Ordering::Relaxed).max(1);
+            if after >= height {
+                self.set_scroll_offset(after - (height - 1));
+            }
+        }
+    }
+
+    async fn handle_key<H: CommandHandler>(&mut self, key: KeyEvent, handler: &mut H) -> KeyAction {
+        if let Some(observer) = self.key_observer.as_mut() {
+            observer(&key);
+        }
+
+        self.focused_error = None;
+        self.key_hint = None;
+        let exit_armed = self.exit_armed.take();
+        #[cfg(feature = "image-banner")]
+        if let Some(splash) = self.splash.as_mut() {
+            splash.ended = true;
+        }
+
+        if !matches!(key.code, KeyCode::Tab | KeyCode::BackTab) {
+            self.completion_cycle = None;
+        }
+
+        if !self.completion_list.is_empty() {
+            if self.handle_completion_list_key(key) {
+                return KeyAction::Continue;
+            }
+            self.completion_list.clear();
+        }
+
+        if self.show_help {
+            self.show_help = false;
+            return KeyAction::Continue;
+        }
+
+        if self.history_search.is_some() {
+            self.handle_history_search_key(key);
+            return KeyAction::Continue;
+        }
+
+        if self.reverse_search.is_some() {
+            self.handle_reverse_search_key(key);
+            return KeyAction::Continue;
+        }
+
+        if (key.code, key.modifiers) == self.history_search_key {
+            return self.run_action(Action::SearchHistory);
+        }
+        if let Some(action) = self.edit_action(&key) {
+            self.run_edit_action(action);
+            return KeyAction::Continue;
+        }
+
+        if self.fold_selection.is_some() {
+            if let Some(action) = self.handle_fold_selection_key(key) {
+                return action;
+            }
+        }
+        if self.error_panel {
+            if let Some(action) = self.handle_error_panel_key(key) {
+                return action;
+            }
+        }
+
+        if let Some(motion) = word_motion(&key) {
+            let target = match motion {
+                WordMotion::Left => prev_word_start(&self.input, self.cursor_chars()),
+                WordMotion::Right => next_word_end(&self.input, self.cursor_chars()),
+            };
+            self.cursor_position = grapheme_at_char(&self.input, target);
+            return KeyAction::Continue;
+        }
+
+        match key.code {
+            _ if self.is_quit_key(&key) => self.quit(&key, exit_armed),
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.input.clear();
+                self.cursor_position = 0;
+                self.history_index = self.history.len();
+                KeyAction::Continue
+            }
+            KeyCode::Enter if self.input_editor.is_some()
+                && key.modifiers.intersects(KeyModifiers::ALT | KeyModifiers::SHIFT) => {
+                self.input.insert(self.cursor_position, '\n');
+                self.cursor_position += 1;
+                KeyAction::Continue
+            }
+            KeyCode::Enter if self.prompt_once.is_some() => {
+                self.answer_prompt_once();
+                KeyAction::Continue
+            }
+            KeyCode::Esc if self.prompt_once.is_some() => {
+                self.input.clear();
+                self.cursor_position = 0;
+                self.cancel_prompt_once();
+                KeyAction::Continue
+            }
+            KeyCode::Enter => {
+                let typed = std::mem::take(&mut self.input);
+                if typed.trim().is_empty() {
+                    self.cursor_position = 0;
+                    match self.empty_submit {
+                        EmptySubmit::Ignore => return KeyAction::Continue,
+                        EmptySubmit::Reprint => {
+                            self.get_message_logger().log(self.prompt.clone());
+                            return KeyAction::Continue;
+                        }
+                        EmptySubmit::Send => {}
+                    }
+                }
+                let (cmd, history_line) = match self.input_transform.as_mut() {
+                    Some(transform) => {
+                        let transformed = transform(&typed);
+                        let history_line = transformed.history.unwrap_or_else(|| typed.clone());
+                        (transformed.line, history_line)
+                    }
+                    None => (typed.clone(), typed.clone()),
+                };
+
+                let annotate = self.annotated_history.is_some() && !history_line.trim().is_empty();
+                let annotated_line = if annotate { Some(history_line.clone()) } else { None };
+                if !history_line.trim().is_empty() {
+                    self.history.push(history_line);
+                }
+                self.history_index = self.history.len();
+
+                if self.transcript_mode {
+                    self.get_message_logger().log(format!("{}{}", self.prompt, typed));
+                }
+
+                self.cursor_position = 0;
+                self.set_scroll_offset(0);
+
+                let submitted = if self.restore_input_on_error { Some(typed) } else { None };
+                let timestamp = SystemTime::now();
+                let result = handler.on_command(cmd).await;
+
+                if let (Some(command), Some(history)) = (annotated_line, self.annotated_history.as_mut()) {
+                    let outcome = match &result {
+                        Ok(_) => CommandOutcome::Succeeded,
+                        Err(e) => CommandOutcome::Failed(e.clone()),
+                    };
+                    history.push(HistoryEntry { command, timestamp, outcome });
+                    trim_history_record(history);
+                }
+
+                match result {
+                    Ok(true) => KeyAction::Exit,
+                    Ok(false) => KeyAction::Continue,
+                    Err(e) => {
+                        self.get_message_logger().error(&e);
+                        if let Some(submitted) = submitted {
+                            self.cursor_position = submitted.len();
+                            self.input = submitted;
+                        }
+                        KeyAction::Continue
+                    }
+                }
+            }
+            KeyCode::Up => {
+                if !self.move_to_line(-1) {
+                    self.navigate_history(HistoryStep::Older);
+                }
+                KeyAction::Continue
+            }
+            KeyCode::Down => {
+                if !self.move_to_line(1) {
+                    self.navigate_history(HistoryStep::Newer);
+                }
+                KeyAction::Continue
+            }
+            KeyCode::Char(_) if key.modifiers.contains(KeyModifiers::CONTROL)
+                && !key.modifiers.contains(KeyModifiers::ALT) => {
+                self.dispatch_unhandled_key(key)
+            }
+            KeyCode::Char('?') if self.input.is_empty() => {
+                self.show_help = true;
+                KeyAction::Continue
+            }
+            // Control characters would take no cell on screen.
+            KeyCode::Char(c) if c.is_control() => KeyAction::Continue,
+            KeyCode::Char(c) => {
+                self.input.insert(self.cursor_position, c);
+                self.cursor_position += c.len_utf8();
+                KeyAction::Continue
+            }
+            // Whole grapheme clusters at a time, so the cursor never ends up
+            // inside one.
+            KeyCode::Backspace => {
+                let start = prev_grapheme(&self.input, self.cursor_position);
+                self.input.replace_range(start..self.cursor_position, "");
+                self.cursor_position = start;
+                KeyAction::Continue
+            }
+            KeyCode::Left => {
+                self.cursor_position = prev_grapheme(&self.input, self.cursor_position);
+                KeyAction::Continue
+            }
+            KeyCode::Right => {
+                self.cursor_position = next_grapheme(&self.input, self.cursor_position);
+                KeyAction::Continue
+            }
+            KeyCode::Tab if self.input.is_empty() => {
+                match self.empty_tab {
+                    EmptyTab::ListAll => {
+                        self.completion_list = handler.complete("", 0);
+                        self.completion_selection = 0;
+                    }
+                    EmptyTab::Indent => {
+                        self.input = " ".repeat(self.tab_width.max(1));
+                        self.cursor_position = self.input.len();
+                    }
+                    EmptyTab::Ignore => {}
+                }
+                KeyAction::Continue
+            }
+            KeyCode::Tab | KeyCode::BackTab if self.completion_cycle.is_some() => {
+                self.step_completion_cycle(key.code == KeyCode::BackTab);
+                KeyAction::Continue
+            }
+            KeyCode::Tab => {
+                let suggestions = handler.complete(&self.input, self.cursor_chars());
+                self.complete(suggestions);
+                KeyAction::Continue
+            }
+            // Starts a cycle from its last candidate.
+            KeyCode::BackTab if self.completion_behavior == CompletionBehavior::Cycle => {
+                let suggestions = handler.complete(&self.input, self.cursor_chars());
+                self.complete(suggestions);
+                if self.completion_cycle.is_some() {
+                    self.step_completion_cycle(true);
+                }
+                KeyAction::Continue
+            }
+            // History lies below the view when newest-first.
+            KeyCode::PageUp | KeyCode::PageDown => {
+                let into_history = (key.code == KeyCode::PageUp) != self.newest_first;
+                self.view.scroll_by(if into_history { 5 } else { -5 });
+                KeyAction::Continue
+            }
+            KeyCode::Home => {
+                self.cursor_position = self.line_start(self.cursor_position);
+                KeyAction::Continue
+            }
+            KeyCode::End => {
+                self.cursor_position = self.line_end(self.cursor_position);
+                KeyAction::Continue
+            }
+            KeyCode::F(n) => match self.function_keys.get(&n).copied() {
+                Some(action) => self.run_action(action),
+                None => self.dispatch_unhandled_key(key),
+            },
+            _ => self.dispatch_unhandled_key(key),
+        }
+    }
+
+    // Text lines the input box shows: one, unless it is an editor.
+    fn input_box_lines(&self) -> u16 {
+        match self.input_editor {
+            Some(max_rows) => (self.input.split('\n').count() as u16).min(max_rows),
+            None => 1,
+        }
+    }
+
+    // Start of the input line `pos` is on. Lines only come from the editor,
+    // so for a single-line input this is 0.
+    fn line_start(&self, pos: usize) -> usize {
+        self.input[..pos].rfind('\n').map_or(0, |newline| newline + 1)
+    }
+
+    // End of the input line `pos` is on, before its newline.
+    fn line_end(&self, pos: usize) -> usize {
+        self.input[pos..].find('\n').map_or(self.input.len(), |end| pos + end)
+    }
+
+    // Cuts `range` of the input into the kill buffer, leaving the cursor
+    // where it was. An empty range keeps the buffer as it is.
+    fn kill(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.cursor_position = range.start;
+        self.kill_buffer = self.input.drain(range).collect();
+    }
+
+    // The cursor's line and display column in the input, from 0.
+    fn cursor_line_column(&self) -> (usize, usize) {
+        let before = &self.input[..self.cursor_position];
+        let column = display_width(&before[self.line_start(self.cursor_position)..]);
+        (before.matches('\n').count(), column)
+    }
+
+    // Moves the cursor `delta` lines up or down, keeping its column where
+    // the line is long enough. False if there is no such line.
+    fn move_to_line(&mut self, delta: isize) -> bool {
+        let (line, column) = self.cursor_line_column();
+        let Some(target) = line.checked_add_signed(delta) else {
+            return false;
+        };
+        let Some(text) = self.input.split('\n').nth(target) else {
+            return false;
+        };
+        let start = self.input.split('\n').take(target).map(|l| l.len() + 1).sum::<usize>();
+        let mut offset = 0;
+        while offset < text.len() {
+            let next = next_grapheme(text, offset);
+            if display_width(&text[..next]) > column {
+                break;
+            }
+            offset = next;
+        }
+        self.cursor_position = start + offset;
+        true
+    }
+
+    // The wheel scrolls the message pane like PageUp/PageDown, in smaller
+    // steps.
+    fn handle_mouse(&mut self, mouse: MouseEvent) -> io::Result<()> {
+        let into_history = match mouse.kind {
+            MouseEventKind::ScrollUp => !self.newest_first,
+            MouseEventKind::ScrollDown => self.newest_first,
+            MouseEventKind::Down(MouseButton::Left) if self.click_select => return self.handle_click(mouse),
+            _ => return Ok(()),
+        };
+        self.view.scroll_by(if into_history { WHEEL_SCROLL_LINES } else { -WHEEL_SCROLL_LINES });
+        Ok(())
+    }
+
+    // Counts clicks on the same cell in quick succession: the second copies
+    // the word under the pointer, the third the whole line, and a fourth
+    // starts over.
+    fn handle_click(&mut self, mouse: MouseEvent) -> io::Result<()> {
+        let now = Instant::now();
+        let count = match self.last_click {
+            Some(last) if (last.column, last.row) == (mouse.column, mouse.row) && now - last.at < MULTI_CLICK_TIME => {
+                last.count % 3 + 1
+            }
+            _ => 1,
+        };
+        self.last_click = Some(Click { at: now, column: mouse.column, row: mouse.row, count });
+
+        let Some((id, row, column)) = self.clicked_row(mouse.column, mouse.row) else {
+            return Ok(());
+        };
+        let text = match count {
+            2 => word_at_column(&row, column).map(|word| row[word].to_string()),
+            3 => {
+                let messages = locks::lock(&self.messages, Rank::Messages).unwrap();
+                messages.iter().find(|e| e.id == id).map(|e| strip_ansi_codes(&e.text))
+            }
+            _ => None,
+        };
+        match text {
+            Some(text) => self.copy(&text),
+            None => Ok(()),
+        }
+    }
+
+    // The entry id, plain text and text column of the message row drawn at
+    // the given cell in the last frame.
+    fn clicked_row(&self, column: u16, row: u16) -> Option<(u64, String, usize)> {
+        let pane = self.message_pane.get();
+        if !pane.contains(Position::new(column, row)) {
+            return None;
+        }
+        let cache = self.render_cache.borrow();
+        let cached = cache.as_ref()?;
+        let index = (row - pane.y) as usize;
+        let line = cached.rows.get(index)?;
+        let text: String = line.spans.iter().map(|span| span.content.as_ref()).collect();
+        let slack = (pane.width as usize).saturating_sub(display_width(&text));
+        let indent = match line.alignment {
+            Some(Alignment::Center) => slack / 2,
+            Some(Alignment::Right) => slack,
+            _ => 0,
+        };
+        let column = ((column - pane.x) as usize).checked_sub(indent)?;
+        Some((cached.owners[index], text, column))
+    }
+
+    fn copy(&mut self, text: &str) -> io::Result<()> {
+        match self.copy_handler.as_mut() {
+            Some(handler) => handler(text),
+            None => {
+                let mut stdout = io::stdout();
+                write!(stdout, "\x1b]52;c;{}\x07", base64(text.as_bytes()))?;
+                stdout.flush()?;
+            }
+        }
+        self.key_hint = Some((format!("copied {} characters", text.chars().count()), Instant::now()));
+        Ok(())
+    }
+
+    fn is_quit_key(&self, key: &KeyEvent) -> bool {
+        let ctrl_c = key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+        (ctrl_c && self.exit.ctrl_c == CtrlC::Quit) || self.exit.quit_keys.contains(&(key.code, key.modifiers))
+    }
+
+    fn quit(&mut self, key: &KeyEvent, armed: Option<Instant>) -> KeyAction {
+        if !self.exit.require_confirmation || armed.is_some_and(|at| at.elapsed() < KEY_HINT_TIME) {
+            return KeyAction::Exit;
+        }
+        let now = Instant::now();
+        self.exit_armed = Some(now);
+        self.key_hint = Some((format!("press {} again to quit", key_label(key.code, key.modifiers)), now));
+        KeyAction::Continue
+    }
+
+    fn complete(&mut self, suggestions: Vec<Completion>) {
+        let values: Vec<String> = suggestions.iter().map(|c| c.value.clone()).collect();
+        let replacement = match (self.completion_behavior, values.len()) {
+            (_, 0) => return,
+            (CompletionBehavior::AcceptFirst, _) | (_, 1) => values[0].clone(),
+            (CompletionBehavior::Cycle, _) => {
+                self.completion_cycle = Some((suggestions, 0));
+                values[0].clone()
+            }
+            (CompletionBehavior::CommonPrefixThenList, _)
+                if common_prefix(&values).len() > self.input.len() =>
+            {
+                common_prefix(&values).to_string()
+            }
+            _ => {
+                self.completion_list = suggestions;
+                self.completion_selection = 0;
+                return;
+            }
+        };
+        self.input = replacement;
+        self.cursor_position = self.input.len();
+    }
+
+    fn step_completion_cycle(&mut self, backward: bool) {
+        let Some((candidates, index)) = self.completion_cycle.as_mut() else {
+            return;
+        };
+        let count = candidates.len();
+        *index = if backward { (*index + count - 1) % count } else { (*index + 1) % count };
+        self.input = candidates[*index].value.clone();
+        self.cursor_position = self.input.len();
+    }
+
+    // While candidates are listed, Up/Down and Tab move the selection, Enter
+    // accepts it and Esc closes the list. Returns false for other keys,
+    // which close the list and then behave as usual.
+    fn handle_completion_list_key(&mut self, key: KeyEvent) -> bool {
+        let count = self.completion_list.len();
+        match key.code {
+            KeyCode::Up | KeyCode::BackTab => {
+                self.completion_selection = (self.completion_selection + count - 1) % count;
+            }
+            KeyCode::Down | KeyCode::Tab => {
+                self.completion_selection = (self.completion_selection + 1) % count;
+            }
+            KeyCode::Enter => {
+                let selected = self.completion_list.swap_remove(self.completion_selection);
+                self.completion_list.clear();
+                self.input = selected.value;
+                self.cursor_position = self.input.len();
+            }
+            KeyCode::Esc => self.completion_list.clear(),
+            _ => return false,
+        }
+        true
+    }
+
+    // `history_index == history.len()` means "past the newest entry", i.e.
+    // the line being typed. The index is clamped first so anything that
+    // shrinks the history can't leave it out of range.
+    fn navigate_history(&mut self, step: HistoryStep) {
+        if self.history.is_empty() {
+            self.history_index = 0;
+            return;
+        }
+
+        let len = self.history.len();
+        let current = self.history_index.min(len);
+        let next = match step {
+            HistoryStep::Older => current.saturating_sub(1),
+            HistoryStep::Newer => (current + 1).min(len),
+        };
+        if next == current {
+            self.history_index = current;
+            return;
+        }
+
+        self.history_index = next;
+        match self.history.get(next) {
+            Some(entry) => self.input = entry.clone(),
+            None => self.input.clear(),
+        }
+        self.cursor_position = self.input.len();
+    }
+
+    fn run_action(&mut self, action: Action) -> KeyAction {
+        match action {
+            Action::ShowHelp => self.show_help = !self.show_help,
+            Action::ClearMessages => {
+                self.get_message_logger().clear();
+                self.set_scroll_offset(0);
+            }
+            Action::ScrollToTop => self.get_message_logger().scroll_to_top(),
+            Action::ScrollToBottom => self.set_scroll_offset(0),
+            Action::ToggleErrorPanel => self.set_error_panel(!self.error_panel),
+            Action::SearchHistory => self.history_search = Some(HistorySearch::default()),
+            Action::ToggleFolds => self.get_message_logger().toggle_folds(),
+            Action::SelectFold => {
+                let newest = self.fold_headers().last().copied();
+                if let Some(id) = newest {
+                    self.select_fold(id);
+                }
+            }
+            Action::CycleLevelFilter => self.set_min_level(match self.min_level {
+                None => Some(Level::Info),
+                Some(Level::Debug | Level::Info) => Some(Level::Warning),
+                Some(Level::Success | Level::Warning) => Some(Level::Error),
+                Some(Level::Error) => None,
+            }),
+            Action::Exit => return KeyAction::Exit,
+        }
+        KeyAction::Continue
+    }
+
+    fn edit_action(&self, key: &KeyEvent) -> Option<EditAction> {
+        self.edit_keys.iter().find(|&&(bound, _)| bound == (key.code, key.modifiers)).map(|&(_, action)| action)
+    }
+
+    fn run_edit_action(&mut self, action: EditAction) {
+        match action {
+            EditAction::CutWordBefore => {
+                let start = grapheme_at_char(&self.input, prev_word_start(&self.input, self.cursor_chars()));
+                self.kill(start..self.cursor_position);
+            }
+            EditAction::CutToLineEnd => self.kill(self.cursor_position..self.line_end(self.cursor_position)),
+            EditAction::CutToLineStart => self.kill(self.line_start(self.cursor_position)..self.cursor_position),
+            EditAction::Paste => {
+                self.input.insert_str(self.cursor_position, &self.kill_buffer);
+                self.cursor_position += self.kill_buffer.len();
+            }
+            EditAction::ReverseSearch => {
+                self.reverse_search = Some(ReverseSearch {
+                    query: String::new(),
+                    found: None,
+                    original: (self.input.clone(), self.cursor_position),
+                });
+            }
+        }
+    }
+
+    fn key_bindings(&self) -> Vec<(String, &'static str)> {
+        let mut bindings = vec![
+            ("Enter".to_string(), "Submit command"),
+            ("Up/Down".to_string(), "Browse history"),
+            ("Left/Right".to_string(), "Move cursor"),
+            ("Ctrl/Alt+Left/Right".to_string(), "Move cursor by word"),
+            ("Home/End".to_string(), "Jump to start/end of input"),
+            ("Tab".to_string(), "Complete (Up/Down/Tab pick from a list)"),
+            ("PageUp/PageDown".to_string(), "Scroll messages"),
+            (key_label(self.history_search_key.0, self.history_search_key.1), "Search history"),
+            ("Ctrl+C".to_string(), match self.exit.ctrl_c {
+                CtrlC::Quit => "Quit",
+                CtrlC::Interrupt => "Discard the typed line",
+            }),
+            ("?".to_string(), "Show this help (at an empty prompt)"),
+        ];
+        if self.mouse_capture && self.alternate_screen {
+            bindings.push(("Mouse wheel".to_string(), "Scroll messages"));
+            if self.click_select {
+                bindings.push(("Double/triple click".to_string(), "Copy a word/line"));
+            }
+        }
+        let edit_rows = self.edit_keys.iter().map(|&((code, modifiers), action)| {
+            (key_label(code, modifiers), action.description())
+        });
+        bindings.splice(4..4, edit_rows);
+        if self.completion_behavior == CompletionBehavior::Cycle {
+            let tab = bindings.iter().position(|(key, _)| key == "Tab").map_or(bindings.len(), |i| i + 1);
+            bindings.insert(tab, ("Tab/Shift+Tab".to_string(), "Next/previous candidate, in place"));
+        }
+        if self.input_editor.is_some() {
+            bindings.insert(1, ("Alt+Enter".to_string(), "Start a new input line"));
+        }
+        for &(code, modifiers) in &self.exit.quit_keys {
+            bindings.push((key_label(code, modifiers), "Quit"));
+        }
+        for (n, action) in &self.function_keys {
+            bindings.push((format!("F{}", n), action.description()));
+        }
+        bindings
+    }
+
+    fn draw_help(&self, f: &mut Frame) {
+        let bindings = self.key_bindings();
+        let key_width = bindings.iter().map(|(k, _)| display_width(k)).max().unwrap_or(0);
+
+        let lines: Vec<Line> = bindings
+            .iter()
+            .map(|(k, desc)| {
+                Line::from(vec![
+                    Span::styled(format!(" {}{}  ", k, " ".repeat(key_width - display_width(k))), Style::default().fg(Color::Yellow)),
+                    Span::styled(*desc, Style::default().fg(Color::White)),
+                ])
+            })
+            .collect();
+
+        let content_width = lines.iter().map(|l| l.width()).max().unwrap_or(0) as u16;
+        let area = centered_rect(f.area(), content_width + 3, lines.len() as u16 + 2);
+
+        let help = Paragraph::new(lines)
+            .block(Block::default()
+                .borders(Borders::ALL)
+                .title("Key bindings (any key to close)")
+                .style(Style::default().fg(Color::Cyan)));
+
+        f.render_widget(Clear, area);
+        f.render_widget(help, area);
+    }
+
+    // Distinct history entries matching `query`, best first; equal scores
+    // keep the most recent entry first.
+    fn history_matches(&self, query: &str) -> Vec<&str> {
+        let mut seen = HashSet::new();
+        let mut matches: Vec<(i64, &str)> = self.history
+            .iter()
+            .rev()
+            .filter(|entry| seen.insert(entry.as_str()))
+            .filter_map(|entry| fuzzy_score(query, entry).map(|score| (score, entry.as_str())))
+            .collect();
+        matches.sort_by_key(|(score, _)| std::cmp::Reverse(*score));
+        matches.into_iter().map(|(_, entry)| entry).collect()
+    }
+
+    fn handle_history_search_key(&mut self, key: KeyEvent) {
+        let count = match &self.history_search {
+            Some(search) => self.history_matches(&search.query).len(),
+            None => return,
+        };
+        let Some(search) = self.history_search.as_mut() else {
+            return;
+        };
+        match key.code {
+            KeyCode::Esc => self.history_search = None,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                self.history_search = None;
+            }
+            KeyCode::Up => search.selection = search.selection.saturating_sub(1),
+            KeyCode::Down if search.selection + 1 < count => search.selection += 1,
+            KeyCode::Backspace => {
+                search.query.pop();
+                search.selection = 0;
+            }
+            KeyCode::Char(c) => {
+                search.query.push(c);
+                search.selection = 0;
+            }
+            KeyCode::Enter => {
+                let query = search.query.clone();
+                let selection = search.selection;
+                if let Some(entry) = self.history_matches(&query).get(selection) {
+                    self.input = entry.to_string();
+                    self.cursor_position = self.input.len();
+                    self.history_index = self.history.len();
+                }
+                self.history_search = None;
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_reverse_search_key(&mut self, key: KeyEvent) {
+        let again = self.edit_action(&key) == Some(EditAction::ReverseSearch);
+        let Some(search) = self.reverse_search.as_mut() else {
+            return;
+        };
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        // Where to look from: the shown match for the next older one, the
+        // newest entry when the query changes.
+        let before = match key.code {
+            _ if again => search.found.unwrap_or(self.history.len()),
+            KeyCode::Char(c) if !ctrl => {
+                search.query.push(c);
+                self.history.len()
+            }
+            KeyCode::Backspace => {
+                search.query.pop();
+                self.history.len()
+            }
+            KeyCode::Enter => {
+                self.reverse_search = None;
+                self.history_index = self.history.len();
+                return;
+            }
+            KeyCode::Esc => return self.cancel_reverse_search(),
+            KeyCode::Char('c') if ctrl => return self.cancel_reverse_search(),
+            _ => return,
+        };
+
+        let found = match search.query.as_str() {
+            "" => None,
+            query => self.history[..before].iter().rposition(|entry| entry.contains(query)),
+        };
+        // Ctrl+R past the oldest match stays on it.
+        if found.is_none() && again {
+            return;
+        }
+        search.found = found;
+        match found {
+            Some(index) => {
+                self.input = self.history[index].clone();
+                self.cursor_position = self.input.find(search.query.as_str()).unwrap_or(0);
+            }
+            None if search.query.is_empty() => {
+                (self.input, self.cursor_position) = search.original.clone();
+            }
+            None => {}
+        }
+    }
+
+    fn cancel_reverse_search(&mut self) {
+        if let Some(search) = self.reverse_search.take() {
+            (self.input, self.cursor_position) = search.original;
+        }
+    }
+
+    // The candidates on show, and the selected one: the list after an
+    // ambiguous Tab, or those being cycled through.
+    fn shown_completions(&self) -> Option<(&[Completion], usize)> {
+        match &self.completion_cycle {
+            _ if !self.completion_list.is_empty() => Some((&self.completion_list, self.completion_selection)),
+            Some((candidates, index)) => Some((candidates, *index)),
+            None => None,
+        }
+    }
+
+    fn completion_list_height(&self) -> u16 {
+        let Some((candidates, _)) = self.shown_completions() else {
+            return 0;
+        };
+        let border = if self.compact { 0 } else { 2 };
+        (candidates.len() as u16).min(MAX_COMPLETION_ROWS) + border
+    }
+
+    // Drawn in `area` when docked, otherwise over the bottom of the message
+    // pane just above the input.
+    fn draw_completion_list(
+        &self,
+        f: &mut Frame,
+        input_area: Rect,
+        docked_area: Rect,
+        (candidates, selected): (&[Completion], usize),
+    ) {
+        let area = if self.docked_completions {
+            docked_area
+        } else {
+            let height = self.completion_list_height().min(input_area.y);
+            Rect {
+                x: input_area.x,
+                y: input_area.y - height,
+                width: input_area.width,
+                height,
+            }
+        };
+
+        let value_width = candidates.iter().map(|c| display_width(&c.value)).max().unwrap_or(0);
+        let items: Vec<ListItem> = candidates
+            .iter()
+            .map(|c| {
+                let padding = " ".repeat(value_width - display_width(&c.value) + 2);
+                ListItem::new(Line::from(vec![
+                    Span::styled(c.value.clone(), Style::default().fg(Color::White)),
+                    Span::raw(padding),
+                    Span::styled(c.description.clone(), Style::default().fg(Color::Gray).add_modifier(Modifier::DIM)),
+                ]))
+            })
+            .collect();
+        let list = if self.compact {
+            List::new(items)
+        } else {
+            List::new(items).block(Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Completions ({})", candidates.len()))
+                .style(Style::default().fg(Color::Cyan)))
+        };
+        let list = list.highlight_style(Style::default().bg(Color::DarkGray));
+
+        let mut state = ListState::default();
+        state.select(Some(selected));
+        f.render_widget(Clear, area);
+        f.render_stateful_widget(list, area, &mut state);
+    }
+
+    fn draw_history_search(&self, f: &mut Frame, search: &HistorySearch) {
+        let screen = f.area();
+        let area = centered_rect(screen, screen.width * 3 / 4, screen.height * 3 / 4);
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .title("History search (Enter to accept, Esc to cancel)")
+            .style(Style::default().fg(Color::Cyan));
+        let inner = block.inner(area);
+        f.render_widget(Clear, area);
+        f.render_widget(block, area);
+
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(1), Constraint::Min(0)])
+            .split(inner);
+
+        let query = Paragraph::new(Line::from(vec![
+            Span::styled("> ", self.prompt_style),
+            Span::styled(search.query.as_str(), Style::default().fg(Color::White)),
+        ]));
+        f.render_widget(query, rows[0]);
+
+        let matches = self.history_matches(&search.query);
+        let count = matches.len();
+        let items: Vec<ListItem> = matches
+            .into_iter()
+            .map(|entry| ListItem::new(entry.to_string()).style(Style::default().fg(Color::White)))
+            .collect();
+
+        let mut state = ListState::default();
+        if count > 0 {
+            state.select(Some(search.selection.min(count - 1)));
+        }
+        let list = List::new(items).highlight_style(Style::default().bg(Color::DarkGray));
+        f.render_stateful_widget(list, rows[1], &mut state);
+
+        let cursor_x = rows[0].x + 2 + search.query.chars().count() as u16;
+        f.set_cursor_position((cursor_x.min(rows[0].right().saturating_sub(1)), rows[0].y));
+    }
+
+    // Ids of the fold headers in the buffer, oldest first.
+    fn fold_headers(&self) -> Vec<u64> {
+        let messages = locks::lock(&self.messages, Rank::Messages).unwrap();
+        messages.iter().filter(|e| e.fold == Some(FoldPart::Header)).map(|e| e.id).collect()
+    }
+
+    // Highlights the fold header with `id` and scrolls it onto the bottom
+    // row.
+    fn select_fold(&mut self, id: u64) {
+        self.fold_selection = Some(id);
+        let messages = locks::lock(&self.messages, Rank::Messages).unwrap();
+        let offset = messages.iter().position(|e| e.id == id).and_then(|index| self.offset_of(&messages, index));
+        drop(messages);
+        if let Some(offset) = offset {
+            self.set_scroll_offset(offset);
+        }
+    }
+
+    // Up and Down move between fold headers, Enter expands or collapses the
+    // selected fold and Escape stops picking. Any other key stops picking
+    // too and then does what it usually does.
+    fn handle_fold_selection_key(&mut self, key: KeyEvent) -> Option<KeyAction> {
+        let selected = self.fold_selection?;
+        let headers = self.fold_headers();
+        let Some(position) = headers.iter().position(|&id| id == selected) else {
+            self.fold_selection = None;
+            return None;
+        };
+        match key.code {
+            KeyCode::Up => self.select_fold(headers[position.saturating_sub(1)]),
+            KeyCode::Down => self.select_fold(headers[(position + 1).min(headers.len() - 1)]),
+            KeyCode::Enter => {
+                let expanded = locks::lock(&self.folds, Rank::Leaf).unwrap().expanded.contains(&selected);
+                self.get_message_logger().set_fold_expanded(selected, !expanded);
+                self.select_fold(selected);
+            }
+            KeyCode::Esc => self.fold_selection = None,
+            _ => {
+                self.fold_selection = None;
+                return None;
+            }
+        }
+        Some(KeyAction::Continue)
+    }
+
+    // The scroll offset that puts the stored entry at `index` on the bottom
+    // row (the top row when newest-first). An entry that isn't shown,
+    // inside a collapsed fold or below the level filter, stands in for the
+    // closest shown entry before it.
+    fn offset_of(&self, messages: &VecDeque<LogEntry>, index: usize) -> Option<usize> {
+        let visible = self.visible_entries(messages);
+        let positions: HashMap<u64, usize> = visible.iter().enumerate().map(|(i, e)| (e.id, i)).collect();
+        let position = messages.range(..=index).rev().find_map(|e| positions.get(&e.id))?;
+        Some(visible.len() - 1 - position)
+    }
+
+    fn error_lines(&self) -> Vec<usize> {
+        let messages = locks::lock(&self.messages, Rank::Messages).unwrap();
+        messages
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| matches!(m.level, Some(Level::Error | Level::Warning)))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    // Returns None for keys the panel doesn't use, so they keep their
+    // normal meaning.
+    fn handle_error_panel_key(&mut self, key: KeyEvent) -> Option<KeyAction> {
+        match key.code {
+            KeyCode::Up => {
+                self.error_selection = self.error_selection.saturating_sub(1);
+            }
+            KeyCode::Down => {
+                let count = self.error_lines().len();
+                if self.error_selection + 1 < count {
+                    self.error_selection += 1;
+                }
+            }
+            KeyCode::Enter => {
+                let lines = self.error_lines();
+                if let Some(&line) = lines.get(self.error_selection) {
+                    // Put the selected line at the bottom of the view so the
+                    // lines leading up to it are visible above.
+                    let messages = locks::lock(&self.messages, Rank::Messages).unwrap();
+                    let offset = self.offset_of(&messages, line);
+                    drop(messages);
+                    if let Some(offset) = offset {
+                        self.set_scroll_offset(offset);
+                    }
+                }
+            }
+            KeyCode::Esc => self.error_panel = false,
+            _ => return None,
+        }
+        Some(KeyAction::Continue)
+    }
+
+    fn draw_error_panel(&self, f: &mut Frame, area: Rect, messages: &VecDeque<LogEntry>) {
+        let items: Vec<ListItem> = messages
+            .iter()
+            .enumerate()
+            .filter(|(_, m)| matches!(m.level, Some(Level::Error | Level::Warning)))
+            .map(|(i, m)| {
+                let (text, style) = entry_parts(m);
+                ListItem::new(Line::from(vec![
+                    Span::styled(format!("{:>4} ", i + 1), Style::default().fg(Color::DarkGray)),
+                    Span::styled(expand_tabs(&text, self.tab_width), style),
+                ]))
+            })
+            .collect();
+
+        let count = items.len();
+        let mut state = ListState::default();
+        if count > 0 {
+            state.select(Some(self.error_selection.min(count - 1)));
+        }
+
+        let panel = List::new(items)
+            .block(Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Errors ({})", count))
+                .style(Style::default().fg(Color::Red)))
+            .highlight_style(Style::default().bg(Color::DarkGray));
+
+        f.render_stateful_widget(panel, area, &mut state);
+    }
+
+    fn dispatch_unhandled_key(&mut self, key: KeyEvent) -> KeyAction {
+        let handled = match self.unhandled_key_handler.as_mut() {
+            Some(handler) => handler(key),
+            None => false,
+        };
+        if !handled && self.unbound_key_hints {
+            let hint = format!("unbound key: {}", key_label(key.code, key.modifiers));
+            self.key_hint = Some((hint, Instant::now()));
+        }
+        KeyAction::Continue
+    }
+
+    // Rows an entry is drawn as: one, or as many as it takes to fit
+    // `wrap_width` cells. With level badges, the text after the badge is
+    // left uncolored and continuation rows are indented past the badge.
+    fn entry_rows(&self, entry: &LogEntry, wrap_width: Option<usize>) -> Vec<Line<'static>> {
+        let (text, style) = entry_parts(entry);
+        let (cleaned, colors) = if !entry.segments.is_empty() {
+            (entry.text.clone(), entry.segments.clone())
+        } else if self.preserve_colors {
+            sgr_runs(&entry.text)
+        } else {
+            Default::default()
+        };
+
+        let indent = " ".repeat(BADGE_WIDTH + 1);
+        let (badge, text, style) = match entry.level.zip(Level::split_prefix(&text)) {
+            _ if !self.level_badges => (None, text, style),
+            Some((level, (_, rest))) => {
+                let badge = Span::styled(format!("{} ", level.badge()), level.badge_style());
+                let text_style = entry.style.unwrap_or(Style::default().fg(Color::White));
+                (Some(badge), rest.trim_start().to_string(), text_style)
+            }
+            // Padding would push a centered or right-aligned line off true.
+            None if entry.alignment != Alignment::Left => (None, text, style),
+            None => (Some(Span::raw(indent.clone())), text, style),
+        };
+        let wrap_width = match badge {
+            Some(_) => wrap_width.map(|w| w.saturating_sub(BADGE_WIDTH + 1)),
+            None => wrap_width,
+        };
+        let timestamp = self.timestamps.then(|| clock_time(entry.timestamp, &self.timestamp_format));
+        let wrap_width = match &timestamp {
+            Some(time) => wrap_width.map(|w| w.saturating_sub(display_width(time) + 1)),
+            None => wrap_width,
+        };
+
+        // The tag and level prefixes only ever come off the front, so the
+        // colors move back by what was removed and on by expanded tabs.
+        let removed = cleaned.len().saturating_sub(text.len());
+        let expanded = |pos: usize| match text.contains('\t') {
+            true => expand_tabs(&text[..pos], self.tab_width).len(),
+            false => pos,
+        };
+        let colors: Vec<(Range<usize>, Style)> = colors
+            .into_iter()
+            .filter(|(range, _)| range.end > removed)
+            .map(|(range, color)| {
+                let range = range.start.saturating_sub(removed)..range.end - removed;
+                (expanded(range.start)..expanded(range.end), style.patch(color))
+            })
+            .collect();
+
+        let text = expand_tabs(&text, self.tab_width);
+        let rows = match wrap_width {
+            Some(width) => wrap_line(&text, width),
+            None => vec![text.clone()],
+        };
+        let mut highlights = Vec::new();
+        let label_end = match &entry.label {
+            Some(label) if text.starts_with(&format!("[{}]", label)) => {
+                let color = locks::lock(&self.labels, Rank::Leaf).unwrap().get(label).copied();
+                let color = color.or(entry.level.map(Level::color)).unwrap_or(DEFAULT_LABEL_COLOR);
+                highlights.push((0..label.len() + 2, Style::default().fg(color)));
+                label.len() + 2
+            }
+            _ => 0,
+        };
+        if self.auto_link {
+            let link_style = style.fg(Color::Blue).add_modifier(Modifier::UNDERLINED);
+            highlights.extend(find_urls(&text).into_iter().filter(|r| r.start >= label_end).map(|r| (r, link_style)));
+        }
+        let highlights = overlay(colors, highlights);
+
+        // Rows are pieces of `text` in order, less whitespace dropped at
+        // the breaks, so each one is found by searching on from the last.
+        let mut pos = 0;
+        rows.into_iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let start = pos + text[pos..].find(row.as_str()).unwrap_or(0);
+                pos = start + row.len();
+
+                let mut spans = Vec::new();
+                if let Some(time) = &timestamp {
+                    spans.push(match i {
+                        0 => Span::styled(format!("{} ", time), TIMESTAMP_STYLE),
+                        _ => Span::raw(" ".repeat(display_width(time) + 1)),
+                    });
+                }
+                if let Some(badge) = &badge {
+                    spans.push(if i == 0 { badge.clone() } else { Span::raw(indent.clone()) });
+                }
+                spans.extend(highlight_spans(row, start, &highlights, style));
+                Line::from(spans).alignment(entry.alignment)
+            })
+            .collect()
+    }
+
+    // When lines come or go while scrolled up (new output evicting old,
+    // folds opening or closing), moves the scroll offset so the entry the
+    // view was on stays where it was, instead of the view sliding. A scroll
+    // made since the last frame wins over the anchor.
+    fn keep_scroll_anchor(&self, messages: &VecDeque<LogEntry>) {
+        let revision = self.view.revision.load(Ordering::Relaxed);
+        let mut offset = self.scroll_offset();
+        let anchor = self.scroll_anchor.get();
+        if anchor.is_some_and(|a| a.revision == revision && a.offset == offset) {
+            return;
+        }
+        if offset == 0 {
+            self.scroll_anchor.set(None);
+            return;
+        }
+
+        let visible = self.visible_entries(messages);
+        if let Some(ScrollAnchor { offset: seen, id: Some(id), .. }) = anchor {
+            if seen == offset {
+                if let Some(i) = visible.iter().position(|e| e.id == id) {
+                    offset = visible.len() - 1 - i;
+                    self.set_scroll_offset(offset);
+                }
+            }
+        }
+        let id = visible.len().checked_sub(offset + 1).map(|i| visible[i].id);
+        self.scroll_anchor.set(Some(ScrollAnchor { revision, offset, id }));
+    }
+
+    // Rows for the visible part of the message pane, with the scroll offset
+    // clamped to the buffer and the largest offset there is room for.
+    // Each row comes with the id of the entry it belongs to.
+    fn message_rows(&self, messages: &VecDeque<LogEntry>, width: usize, available_height: usize) -> (Vec<(u64, Line<'static>)>, usize, usize) {
+        let messages = &self.visible_entries(messages)[..];
+        let total_messages = messages.len();
+        let (rows, clamped_scroll, max_scroll) = if self.word_wrap {
+            self.wrapped_rows(messages, width, available_height)
+        } else {
+            let max_scroll = total_messages.saturating_sub(available_height);
+
+            let clamped_scroll = self.scroll_offset().min(max_scroll);
+
+            let start_index = if total_messages > available_height {
+                total_messages - available_height - clamped_scroll
+            } else {
+                0
+            };
+
+            let rows_of = |m: &Cow<'_, LogEntry>| {
+                let id = m.id;
+                self.entry_rows(m, None).into_iter().map(move |row| (id, row))
+            };
+            let rows: Vec<(u64, Line<'static>)> = if self.newest_first {
+                messages
+                    .iter()
+                    .rev()
+                    .skip(clamped_scroll)
+                    .take(available_height)
+                    .flat_map(rows_of)
+                    .collect()
+            } else {
+                messages
+                    .iter()
+                    .skip(start_index)
+                    .take(available_height)
+                    .flat_map(rows_of)
+                    .collect()
+            };
+            (rows, clamped_scroll, max_scroll)
+        };
+
+        let row_count = rows.len();
+        let rows = rows
+            .into_iter()
+            .enumerate()
+            .map(|(i, (id, mut row))| {
+                let age = if self.newest_first { i } else { row_count - 1 - i };
+                if self.recency_fade && age > 0 {
+                    // Linear fade down to FADE_FLOOR brightness on the oldest row.
+                    let factor = 1.0 - (1.0 - FADE_FLOOR) * age as f32 / (row_count - 1) as f32;
+                    for span in &mut row.spans {
+                        span.style = span.style.fg(dim_color(span.style.fg.unwrap_or(Color::Reset), factor));
+                    }
+                }
+                (id, row)
+            })
+            .collect();
+        (rows, clamped_scroll, max_scroll)
+    }
+
+    // The entries the message pane shows, with each fold header turned into
+    // its "▸ label (N lines)" line and the members of collapsed folds left
+    // out. The scroll offset counts these, not the stored entries. Members
+    // whose header has been trimmed away are shown as plain lines.
+    fn visible_entries<'a>(&self, messages: &'a VecDeque<LogEntry>) -> Vec<Cow<'a, LogEntry>> {
+        let shown = |entry: &LogEntry| match (entry.level, self.min_level) {
+            (Some(level), Some(min)) => level.severity() >= min.severity(),
+            _ => true,
+        };
+        if !messages.iter().any(|e| e.fold == Some(FoldPart::Header)) {
+            return messages.iter().filter(|e| shown(e)).map(Cow::Borrowed).collect();
+        }
+
+        let folds = locks::lock(&self.folds, Rank::Leaf).unwrap();
+        let mut sizes: HashMap<u64, usize> = HashMap::new();
+        for entry in messages {
+            if let Some(FoldPart::Member(id)) = entry.fold {
+                *sizes.entry(id).or_default() += 1;
+            }
+        }
+
+        let mut headers = HashSet::new();
+        messages
+            .iter()
+            .filter_map(|entry| match entry.fold {
+                Some(FoldPart::Header) => {
+                    headers.insert(entry.id);
+                    let marker = if folds.expanded.contains(&entry.id) { '▾' } else { '▸' };
+                    let size = sizes.get(&entry.id).copied().unwrap_or(0);
+                    let mut header = entry.clone();
+                    header.text = format!("{} {} ({} lines)", marker, strip_ansi_codes(&entry.text), size);
+                    header.style.get_or_insert(Style::default().fg(Color::Cyan));
+                    Some(Cow::Owned(header))
+                }
+                Some(FoldPart::Member(id)) if headers.contains(&id) && !folds.expanded.contains(&id) => None,
+                _ if !shown(entry) => None,
+                _ => Some(Cow::Borrowed(entry)),
+            })
+            .collect()
+    }
+
+    // With wrapping the scroll offset still counts messages: the message
+    // `offset` entries from the tail sits on the bottom row and earlier ones
+    // fill the rows above it, the topmost possibly cut off.
+    fn wrapped_rows(&self, messages: &[Cow<'_, LogEntry>], width: usize, height: usize) -> (Vec<(u64, Line<'static>)>, usize, usize) {
+        let rows_of = |m: &LogEntry| self.entry_rows(m, Some(width));
+
+        // The furthest we can scroll is the point where the oldest message
+        // reaches the top row.
+        let total = messages.len();
+        let mut rows = 0;
+        let mut first_full_page = total;
+        for (i, m) in messages.iter().enumerate() {
+            rows += rows_of(m).len();
+            if rows >= height {
+                first_full_page = i;
+                break;
+            }
+        }
+        let max_scroll = total.saturating_sub(first_full_page + 1);
+        let clamped_scroll = self.scroll_offset().min(max_scroll);
+
+        let mut items = Vec::new();
+        if self.newest_first {
+            // Mirror image: the message `offset` entries from the tail sits
+            // on the top row and older ones fill the rows below it.
+            for m in messages.iter().rev().skip(clamped_scroll) {
+                for row in rows_of(m) {
+                    if items.len() == height {
+                        break;
+                    }
+                    items.push((m.id, row));
+                }
+                if items.len() == height {
+                    break;
+                }
+            }
+            return (items, clamped_scroll, max_scroll);
+        }
+
+        for m in messages.iter().take(total - clamped_scroll).rev() {
+            let mut message_rows = rows_of(m);
+            while let Some(row) = message_rows.pop() {
+                if items.len() == height {
+                    break;
+                }
+                items.push((m.id, row));
+            }
+            if items.len() == height {
+                break;
+            }
+        }
+        items.reverse();
+        (items, clamped_scroll, max_scroll)
+    }
+
+    fn draw_context(&self, f: &mut Frame, area: Rect, messages: &VecDeque<LogEntry>) {
+        let skip = messages.len().saturating_sub(self.context_lines);
+        let items: Vec<ListItem> = messages
+            .iter()
+            .skip(skip)
+            .flat_map(|m| self.entry_rows(m, None))
+            .map(ListItem::new)
+            .collect();
+
+        let context = if self.compact {
+            List::new(items)
+        } else {
+            List::new(items).block(Block::default()
+                .borders(Borders::TOP)
+                .title("Latest")
+                .style(Style::default().fg(Color::Cyan)))
+        };
+        f.render_widget(context, area);
+    }
+
+    /// Renders one frame. `run` calls this itself; it is public so the UI
+    /// can be drawn onto other backends, e.g. `TestBackend` in benchmarks.
+    pub fn draw(&self, f: &mut Frame) {
+        let inline_input = self.transcript_mode || self.compact;
+        let input_height = if inline_input { 1 } else { self.input_box_lines() + 2 };
+        // Rows/columns taken by the message pane's border.
+        let pane_border = if self.compact { 0 } else { 2 };
+
+        let scrolled = self.scroll_offset().min(self.view.max_scroll.load(Ordering::Relaxed)) > 0;
+        let context_height = if scrolled && self.context_lines > 0 {
+            self.context_lines as u16 + if self.compact { 0 } else { 1 }
+        } else {
+            0
+        };
+
+        let docked_height = if self.docked_completions { self.completion_list_height() } else { 0 };
+        let splash_height = self.splash_height();
+
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Length(splash_height),
+                Constraint::Min(3),
+                Constraint::Length(context_height),
+                Constraint::Length(input_height),
+                Constraint::Length(docked_height),
+            ])
+            .split(f.area());
+        let input_area = chunks[3];
+
+        #[cfg(feature = "image-banner")]
+        if let Some(splash) = &self.splash {
+            splash.area.set((splash_height > 0).then_some(chunks[0]));
+            for position in chunks[0].positions() {
+                f.buffer_mut()[position].set_skip(true);
+            }
+        }
+
+        let messages = locks::lock(&self.messages, Rank::Messages).unwrap();
+
+        if context_height > 0 {
+            self.draw_context(f, chunks[2], &messages);
+        }
+
+        let message_area = if self.error_panel {
+            let columns = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([
+                    Constraint::Min(20),
+                    Constraint::Length(ERROR_PANEL_WIDTH),
+                ])
+                .split(chunks[1]);
+            self.draw_error_panel(f, columns[1], &messages);
+            columns[0]
+        } else {
+            chunks[1]
+        };
+
+        let available_height = message_area.height.saturating_sub(pane_border) as usize;
+        self.view.page_height.store(available_height, Ordering::Relaxed);
+        self.keep_scroll_anchor(&messages);
+        let total_messages = messages.len();
+
+        let width = message_area.width.saturating_sub(pane_border) as usize;
+        let inner = if self.compact { message_area } else { message_area.inner(Margin { horizontal: 1, vertical: 1 }) };
+        self.message_pane.set(inner);
+        let key = RenderKey {
+            revision: self.view.revision.load(Ordering::Relaxed),
+            scroll_offset: self.scroll_offset(),
+            width,
+            height: available_height,
+            options: [
+                self.word_wrap,
+                self.newest_first,
+                self.level_badges,
+                self.auto_link,
+                self.recency_fade,
+                self.preserve_colors,
+                self.timestamps,
+            ],
+            tab_width: self.tab_width,
+            min_level: self.min_level,
+        };
+        let mut cache = self.render_cache.borrow_mut();
+        if !matches!(&*cache, Some(cached) if cached.key == key) {
+            let (rows, clamped_scroll, max_scroll) = self.message_rows(&messages, width, available_height);
+            let (owners, rows) = rows.into_iter().unzip();
+            *cache = Some(RenderCache { key, rows, owners, clamped_scroll, max_scroll });
+        }
+        let cached = cache.as_ref().unwrap();
+        let (clamped_scroll, max_scroll) = (cached.clamped_scroll, cached.max_scroll);
+        let items: Vec<ListItem> = cached
+            .rows
+            .iter()
+            .zip(&cached.owners)
+            .map(|(row, &id)| {
+                let item = ListItem::new(row.clone());
+                if self.fold_selection == Some(id) {
+                    item.style(Style::default().bg(Color::DarkGray))
+                } else {
+                    item
+                }
+            })
+            .collect();
+        drop(cache);
+
+        self.view.max_scroll.store(max_scroll, Ordering::Relaxed);
+
+        let mut segments = vec![TitleSegment::new("R-Term", u8::MAX)];
+        if self.activity_spinner {
+            if let Some(frame) = messages.back().and_then(|newest| spinner_frame(newest.timestamp)) {
+                segments.push(TitleSegment::new(frame.to_string(), 2));
+            }
+        }
+        if let Some(min) = self.min_level {
+            segments.push(TitleSegment::new(format!("{}+", min.prefix()), 3));
+        }
+        if clamped_scroll > 0 {
+            let arrow = if self.newest_first { '↓' } else { '↑' };
+            segments.push(TitleSegment::new(format!("({}{})", arrow, clamped_scroll), 1));
+        }
+        if self.show_count {
+            segments.push(TitleSegment::new(format!("{}/{}", total_messages, MAX_MESSAGES), 0));
+        }
+        let title = fit_title(segments, message_area.width.saturating_sub(pane_border) as usize);
+
+        let messages_list = if self.compact {
+            List::new(items)
+        } else {
+            List::new(items)
+                .block(Block::default()
+                    .borders(Borders::ALL)
+                    .title(title)
+                    .style(Style::default().fg(Color::Cyan)))
+        };
+
+        f.render_widget(messages_list, message_area);
+
+        let placeholder = locks::lock(&self.placeholder, Rank::Leaf).unwrap();
+        let editor = self.input_editor.is_some() && !inline_input;
+        let line_count = self.input.split('\n').count();
+        let gutter_width = if editor { line_count.to_string().len() + 1 } else { 0 };
+        let gutter = |number: usize| {
+            Span::styled(format!("{:>width$} ", number, width = gutter_width - 1), Style::default().fg(Color::DarkGray))
+        };
+        let prompt_display_width = display_width(&self.current_prompt()) + display_width(&self.prompt_separator);
+
+        let input_lines: Vec<Line> = if self.input.is_empty() && !placeholder.is_empty() {
+            let mut spans = vec![
+                Span::styled(self.current_prompt(), self.prompt_style),
+                Span::styled(self.prompt_separator.as_str(), self.prompt_separator_style),
+                Span::styled(placeholder.as_str(), Style::default().fg(Color::DarkGray).add_modifier(Modifier::DIM)),
+            ];
+            if editor {
+                spans.insert(0, gutter(1));
+            }
+            vec![Line::from(spans)]
+        } else {
+            self.input
+                .split('\n')
+                .enumerate()
+                .map(|(i, text)| {
+                    let mut spans = Vec::new();
+                    if editor {
+                        spans.push(gutter(i + 1));
+                    }
+                    if i == 0 {
+                        spans.push(Span::styled(self.current_prompt(), self.prompt_style));
+                        spans.push(Span::styled(self.prompt_separator.as_str(), self.prompt_separator_style));
+                    } else {
+                        spans.push(Span::raw(" ".repeat(prompt_display_width)));
+                    }
+                    spans.push(Span::styled(text, self.input_style));
+                    Line::from(spans)
+                })
+                .collect()
+        };
+
+        // Scrolled just far enough to keep the cursor's line in the box.
+        let (cursor_line, cursor_column) = self.cursor_line_column();
+        let visible_lines = if editor { self.input_box_lines() as usize } else { 1 };
+        let scroll = cursor_line.saturating_sub(visible_lines - 1);
+
+        let (input, border) = if inline_input {
+            (Paragraph::new(input_lines).scroll((scroll as u16, 0)).style(Style::default().fg(Color::Green)), 0)
+        } else {
+            let mut title = "Input".to_string();
+            if editor {
+                title.push_str(&format!(" · Ln {}, Col {}", cursor_line + 1, cursor_column + 1));
+            }
+            if let Some((hint, at)) = &self.key_hint {
+                if at.elapsed() < KEY_HINT_TIME {
+                    title.push_str(&format!(" · {}", hint));
+                }
+            }
+            let input = Paragraph::new(input_lines)
+                .scroll((scroll as u16, 0))
+                .block(Block::default()
+                    .borders(Borders::ALL)
+                    .title(title)
+                    .style(Style::default().fg(Color::Green)));
+            (input, 1)
+        };
+
+        f.render_widget(input, input_area);
+
+        if editor && line_count > visible_lines {
+            let mut state = ScrollbarState::new(line_count - visible_lines + 1).position(scroll);
+            let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight).begin_symbol(None).end_symbol(None);
+            f.render_stateful_widget(scrollbar, input_area.inner(Margin { vertical: 1, horizontal: 0 }), &mut state);
+        }
+
+        if let Some(shown) = self.shown_completions() {
+            self.draw_completion_list(f, input_area, chunks[4], shown);
+        }
+
+        let cursor_x = input_area.x + (gutter_width + prompt_display_width + cursor_column) as u16 + border;
+        let cursor_y = input_area.y + (cursor_line - scroll) as u16 + border;
+        f.set_cursor_position((cursor_x, cursor_y));
+
+        if let Some(search) = &self.history_search {
+            self.draw_history_search(f, search);
+        }
+
+        if self.show_help {
+            self.draw_help(f);
+        }
+    }
+}
+
+// `time` in the local time zone, written out as `format`, which
+// `set_timestamp_format` has checked.
+fn clock_time(time: SystemTime, format: &str) -> String {
+    let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as i64;
+    let local = secs + utc_offset(secs);
+    let (year, month, day) = civil_date(local.div_euclid(86_400));
+    let of_day = local.rem_euclid(86_400) as u32;
+    let time = DateTime {
+        year,
+        month,
+        day,
+        hour: of_day / 3600,
+        minute: of_day / 60 % 60,
+        second: of_day % 60,
+        millis: since_epoch.subsec_millis(),
+    };
+    format_timestamp(format, &time).unwrap_or_default()
+}
+
+// Year, month and day of the date `days` after 1970-01-01 in the Gregorian
+// calendar (Howard Hinnant's `civil_from_days`).
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months count from March, so the leap day comes last.
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    (year_of_era + era * 400 + (month <= 2) as i64, month, day)
+}
+
+// Seconds the local time zone is ahead of UTC at `secs`.
+#[cfg(unix)]
+fn utc_offset(secs: i64) -> i64 {
+    let time = secs as libc::time_t;
+    // localtime_r only writes to `tm`, which is plain data.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+// Without a time zone database to ask, timestamps are in UTC.
+#[cfg(not(unix))]
+fn utc_offset(_secs: i64) -> i64 {
+    0
+}
+
+// Drops the oldest annotated entries beyond what a history file keeps.
+fn trim_history_record(record: &mut Vec<HistoryEntry>) {
+    let excess = record.len().saturating_sub(HISTORY_FILE_CAPACITY);
+    record.drain(..excess);
+}
+
+// The spinner frame for now, or None once `last_log` is more than
+// ACTIVITY_WINDOW ago. Redrawing on the idle tick animates it.
+fn spinner_frame(last_log: SystemTime) -> Option<char> {
+    let since = last_log.elapsed().unwrap_or_default();
+    if since > ACTIVITY_WINDOW {
+        return None;
+    }
+    let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+    let frame = now.as_millis() / SPINNER_FRAME_TIME.as_millis();
+    Some(SPINNER_FRAMES[frame as usize % SPINNER_FRAMES.len()])
+}
+
+// How `MessageLogger::store_lines` marks the lines it stores.
+#[derive(Default)]
+struct LineOptions<'a> {
+    style: Option<Style>,
+    label: Option<&'a str>,
+    pinned: bool,
+    segments: Vec<(Range<usize>, Style)>,
+    alignment: Alignment,
+}
+
+// What the cached message rows depend on besides the buffer itself.
+#[derive(PartialEq, Eq)]
+struct RenderKey {
+    revision: u64,
+    scroll_offset: usize,
+    width: usize,
+    height: usize,
+    options: [bool; 7],
+    tab_width: usize,
+    min_level: Option<Level>,
+}
+
+// The last press of the left mouse button, for spotting double and triple
+// clicks.
+#[derive(Clone, Copy)]
+struct Click {
+    at: Instant,
+    column: u16,
+    row: u16,
+    count: u8,
+}
+
+// The entry a scrolled-up view was showing at the scroll offset, i.e. on
+// its bottom row (its top row when newest-first), as of the last frame.
+#[derive(Clone, Copy)]
+struct ScrollAnchor {
+    revision: u64,
+    offset: usize,
+    id: Option<u64>,
+}
+
+// Message rows from the last frame, reused while nothing they depend on has
+// changed, e.g. while scrolled up with no new output.
+struct RenderCache {
+    key: RenderKey,
+    rows: Vec<Line<'static>>,
+    // The id of the entry each row belongs to.
+    owners: Vec<u64>,
+    clamped_scroll: usize,
+    max_scroll: usize,
+}
+
+// A piece of the message pane title.
+struct TitleSegment {
+    text: String,
+    // Segments with the lowest priority are dropped first when the title
+    // doesn't fit.
+    priority: u8,
+}
+
+impl TitleSegment {
+    fn new(text: impl Into<String>, priority: u8) -> Self {
+        Self { text: text.into(), priority }
+    }
+}
+
+// Joins the segments with spaces, dropping the lowest-priority ones (the
+// later of equal ones first) until the result fits in `width` cells.
+fn fit_title(mut segments: Vec<TitleSegment>, width: usize) -> String {
+    loop {
+        let title = segments.iter().map(|s| s.text.as_str()).collect::<Vec<_>>().join(" ");
+        if display_width(&title) <= width || segments.len() <= 1 {
+            return title;
+        }
+        let lowest = segments
+            .iter()
+            .enumerate()
+            .rev()
+            .min_by_key(|(_, s)| s.priority)
+            .map(|(i, _)| i)
+            .unwrap();
+        segments.remove(lowest);
+    }
+}
+
+fn centered_rect(area: Rect, width: u16, height: u16) -> Rect {
+    let width = width.min(area.width);
+    let height = height.min(area.height);
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// A submitted command as recorded by annotated history.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// As recorded in history, i.e. after any input transform's override.
+    pub command: String,
+    pub timestamp: SystemTime,
+    pub outcome: CommandOutcome,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CommandOutcome {
+    Succeeded,
+    /// The command handler returned this error.
+    Failed(String),
+}
+
+/// What an input transform turns a submitted line into.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TransformedInput {
+    /// Passed to the command handler.
+    pub line: String,
+    /// Recorded in history instead of the typed line, if set.
+    pub history: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum HistoryStep {
+    Older,
+    Newer,
+}
+
+#[derive(Default)]
+struct HistorySearch {
+    query: String,
+    selection: usize,
+}
+
+// Readline-style Ctrl+R: the input shows the newest history entry
+// containing `query`; Ctrl+R again moves to older ones.
+struct ReverseSearch {
+    query: String,
+    // Index in `history` of the entry shown.
+    found: Option<usize>,
+    // The input and cursor from before the search, for Esc.
+    original: (String, usize),
+}
+
+enum KeyAction {
+    Continue,
+    Exit,
+}
+
+/// Outcome of logging a message, for callers that want to back off when
+/// the scrollback is saturated.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogStatus {
+    Accepted = 0,
+    /// Stored, but the buffer was full and older lines were evicted.
+    Evicted = 1,
+    /// Not stored (the buffer lock was poisoned, or the buffer is full
+    /// under `TrimStrategy::KeepHead`).
+    Dropped = 2,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Level {
+    Info,
+    Success,
+    Warning,
+    Error,
+    Debug,
+}
+
+impl Level {
+    /// Prefix `MessageLogger` writes for this level.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Level::Info => "[INFO]",
+            Level::Success => "[SUCCESS]",
+            Level::Warning => "[WARNING]",
+            Level::Error => "[ERROR]",
+            Level::Debug => "[DEBUG]",
+        }
+    }
+
+    // Recognizes our own prefixes and the symbols backends use instead.
+    // Returns the level and the rest of the line.
+    fn split_prefix(line: &str) -> Option<(Level, &str)> {
+        let prefixes: [(&[&str], Level); 5] = [
+            (&["[ERROR]", "✗"], Level::Error),
+            (&["[✓]", "[SUCCESS]"], Level::Success),
+            (&["[INFO]", "ℹ"], Level::Info),
+            (&["[WARNING]", "⚠"], Level::Warning),
+            (&["[DEBUG]"], Level::Debug),
+        ];
+        prefixes.iter().find_map(|(ps, level)| {
+            ps.iter().find_map(|p| line.strip_prefix(p)).map(|rest| (*level, rest))
+        })
+    }
+
+    fn from_line(line: &str) -> Option<Level> {
+        Self::split_prefix(line).map(|(level, _)| level)
+    }
+
+    // Order for `TerminalUI::set_min_level`, least important first.
+    fn severity(self) -> u8 {
+        match self {
+            Level::Debug => 0,
+            Level::Info => 1,
+            Level::Success => 2,
+            Level::Warning => 3,
+            Level::Error => 4,
+        }
+    }
+
+    // Every badge is BADGE_WIDTH cells wide so message text lines up.
+    fn badge(self) -> &'static str {
+        match self {
+            Level::Info => "[I]",
+            Level::Success => "[✓]",
+            Level::Warning => "[!]",
+            Level::Error => "[✗]",
+            Level::Debug => "[D]",
+        }
+    }
+
+    fn badge_style(self) -> Style {
+        match self {
+            Level::Debug => Style::default().fg(Color::DarkGray),
+            level => Style::default().fg(level.color()),
+        }
+    }
+
+    fn color(self) -> Color {
+        match self {
+            Level::Info => Color::Cyan,
+            Level::Success => Color::Green,
+            Level::Warning => Color::Yellow,
+            Level::Error => Color::Red,
+            Level::Debug => Color::Magenta,
+        }
+    }
+}
+
+/// A line in the scrollback. `text` is the line as logged, prefix and any
+/// ANSI codes included; the other fields are worked out when it is stored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogEntry {
+    pub text: String,
+    /// From a level prefix such as `[ERROR]` or `✗`.
+    pub level: Option<Level>,
+    pub timestamp: SystemTime,
+    /// Banner tag such as `RUST3`, without brackets. Not shown on screen.
+    pub tag: Option<String>,
+    /// Increases with every stored line; 0 for lines the terminal
+    /// generates itself, like the elision marker.
+    pub id: u64,
+    /// Drawn instead of the color the level or tag would give.
+    pub style: Option<Style>,
+    /// Category given to `MessageLogger::log_labeled`, without brackets.
+    /// Its `[label]` prefix is drawn in the label's color.
+    pub label: Option<String>,
+    /// Set for the lines of a block started with `MessageLogger::fold_begin`.
+    pub fold: Option<FoldPart>,
+    /// Kept when older lines are evicted; see `MessageLogger::log_pinned`.
+    pub pinned: bool,
+    /// Styles for byte ranges of `text`, from `MessageLogger::log_spans`.
+    pub segments: Vec<(Range<usize>, Style)>,
+    /// Where the line sits in the pane; see `MessageLogger::log_aligned`.
+    pub alignment: Alignment,
+}
+
+impl LogEntry {
+    pub fn new(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let cleaned = strip_ansi_codes(&text);
+        let tag = banner_tag(&cleaned).map(str::to_string);
+        let level = if tag.is_some() { None } else { Level::from_line(&cleaned) };
+        Self {
+            text,
+            level,
+            timestamp: SystemTime::now(),
+            tag,
+            id: 0,
+            style: None,
+            label: None,
+            fold: None,
+            pinned: false,
+            segments: Vec::new(),
+            alignment: Alignment::Left,
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.level == Some(Level::Error)
+    }
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+/// Where `TerminalUI::run_with` sends what the user does.
+pub trait CommandHandler {
+    /// Handles a submitted line. `Ok(true)` ends the session; an error is
+    /// shown in the message pane.
+    fn on_command(&mut self, line: String) -> impl Future<Output = Result<bool, String>>;
+
+    /// Candidates for Tab, given the input and the cursor position in it
+    /// (in chars).
+    fn complete(&mut self, buffer: &str, cursor: usize) -> Vec<Completion>;
+}
+
+/// Where `TerminalUI::run_on` reads terminal events from.
+pub trait EventSource {
+    /// Whether an event is ready, waiting up to `timeout` for one.
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool>;
+
+    /// The next event, blocking until there is one.
+    fn read(&mut self) -> io::Result<Event>;
+}
+
+/// The real terminal's events, through crossterm.
+pub struct CrosstermEvents;
+
+impl EventSource for CrosstermEvents {
+    fn poll(&mut self, timeout: Duration) -> io::Result<bool> {
+        event::poll(timeout)
+    }
+
+    fn read(&mut self) -> io::Result<Event> {
+        event::read()
+    }
+}
+
+// The pair of closures `TerminalUI::run` takes.
+struct Callbacks<FInput, FTab> {
+    on_command: FInput,
+    on_autocomplete: FTab,
+}
+
+impl<FInput, Fut, FTab> CommandHandler for Callbacks<FInput, FTab>
+where
+    FInput: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<bool, String>>,
+    FTab: FnMut(&str, usize) -> Vec<Completion>,
+{
+    fn on_command(&mut self, line: String) -> impl Future<Output = Result<bool, String>> {
+        (self.on_command)(line)
+    }
+
+    fn complete(&mut self, buffer: &str, cursor: usize) -> Vec<Completion> {
+        (self.on_autocomplete)(buffer, cursor)
+    }
+}
+
+/// A completion candidate. The description is shown dimmed beside the
+/// value when candidates are listed; it may be empty.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Completion {
+    pub value: String,
+    pub description: String,
+}
+
+impl Completion {
+    pub fn new(value: impl Into<String>, description: impl Into<String>) -> Self {
+        Self { value: value.into(), description: description.into() }
+    }
+}
+
+impl From<String> for Completion {
+    fn from(value: String) -> Self {
+        Self { value, description: String::new() }
+    }
+}
+
+impl From<&str> for Completion {
+    fn from(value: &str) -> Self {
+        Self::from(value.to_string())
+    }
+}
+
+/// Where a line sits in a folded block.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FoldPart {
+    /// The block's header, whose text is the label. Its `id` identifies the
+    /// fold.
+    Header,
+    /// A line of the block whose header has this id.
+    Member(u64),
+}
+
+#[derive(Default)]
+struct FoldState {
+    // Header id of the block lines are currently going into.
+    open: Option<u64>,
+    // Folds are collapsed unless listed here.
+    expanded: HashSet<u64>,
+}
+
+/// What Tab does with the candidates the completion handler returns.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CompletionBehavior {
+    /// Replace the input with the first candidate.
+    AcceptFirst,
+    /// Accept a sole candidate, otherwise extend the input to the
+    /// candidates' common prefix, or list them if that adds nothing.
+    #[default]
+    CommonPrefixThenList,
+    /// Accept a sole candidate, otherwise only list them.
+    ListOnly,
+    /// Replace the input with the first candidate, then with the next one
+    /// on each further Tab, or the previous one on Shift+Tab, wrapping
+    /// around. The candidates are listed meanwhile, with the one in the
+    /// input selected. Any other key keeps it and closes the list.
+    Cycle,
+}
+
+/// What Enter does on an empty or whitespace-only input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptySubmit {
+    /// Do nothing; the command handler isn't called.
+    #[default]
+    Ignore,
+    /// Pass the line to the command handler like any other.
+    Send,
+    /// Echo the bare prompt into the scrollback, like a shell does.
+    Reprint,
+}
+
+/// How the user ends the session.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExitConfig {
+    /// Keys that quit, besides Ctrl+C when `ctrl_c` is `CtrlC::Quit`.
+    pub quit_keys: Vec<(KeyCode, KeyModifiers)>,
+    /// Quit only when the key is pressed a second time within two
+    /// seconds; the first press shows a hint in the input box title.
+    pub require_confirmation: bool,
+    pub ctrl_c: CtrlC,
+}
+
+impl Default for ExitConfig {
+    fn default() -> Self {
+        Self { quit_keys: Vec::new(), require_confirmation: false, ctrl_c: CtrlC::Quit }
+    }
+}
+
+/// What Ctrl+C does.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CtrlC {
+    /// Quit, like the other quit keys.
+    #[default]
+    Quit,
+    /// Discard the line being typed, like an interrupt in a shell.
+    Interrupt,
+}
+
+/// What Tab does on an empty input.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EmptyTab {
+    /// List every candidate the handler offers without inserting any.
+    #[default]
+    ListAll,
+    /// Insert spaces up to the first tab stop.
+    Indent,
+    /// Do nothing; the handler isn't asked for candidates.
+    Ignore,
+}
+
+/// What to evict once the scrollback reaches `MAX_MESSAGES`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TrimStrategy {
+    #[default]
+    DropOldest,
+    /// Keeps the first `head` lines and the most recent ones, collapsing
+    /// everything in between into a single "lines elided" marker.
+    DropMiddle { head: usize },
+    /// Keeps what is already stored and drops new lines.
+    KeepHead,
+}
+
+#[derive(Default)]
+struct TrimState {
+    strategy: TrimStrategy,
+    // Whether the pinned-line limit has been reported since the last reset.
+    pin_limit_warned: bool,
+    // Position of the elision marker (DropMiddle only) and how many lines
+    // it stands for.
+    marker: Option<usize>,
+    elided: usize,
+}
+
+impl TrimState {
+    fn reset(&mut self) {
+        self.marker = None;
+        self.elided = 0;
+        self.pin_limit_warned = false;
+    }
+
+    // Stores `line` before `index`, evicting per the strategy when full.
+    // Returns the status and the position the line was stored at.
+    fn store(&mut self, msgs: &mut VecDeque<LogEntry>, index: usize, line: LogEntry) -> (LogStatus, usize) {
+        let mut index = index.min(msgs.len());
+        let mut status = LogStatus::Accepted;
+
+        if msgs.len() >= MAX_MESSAGES {
+            let removed = match self.strategy {
+                TrimStrategy::KeepHead => return (LogStatus::Dropped, index),
+                TrimStrategy::DropOldest => {
+                    let oldest = msgs.iter().position(|e| !e.pinned).unwrap_or(0);
+                    msgs.remove(oldest);
+                    oldest
+                }
+                TrimStrategy::DropMiddle { head } => self.elide(msgs, head),
+            };
+            if removed < index {
+                index -= 1;
+            }
+            status = LogStatus::Evicted;
+        }
+
+        if let Some(marker) = self.marker {
+            if index <= marker {
+                self.marker = Some(marker + 1);
+            }
+        }
+        msgs.insert(index, line);
+        (status, index)
+    }
+
+    // Removes one line next to the elision marker, creating the marker on
+    // first use. Returns the position of the removed line.
+    fn elide(&mut self, msgs: &mut VecDeque<LogEntry>, head: usize) -> usize {
+        let marker = match self.marker {
+            Some(marker) => marker,
+            None => {
+                let marker = head.min(MAX_MESSAGES - 2);
+                self.marker = Some(marker);
+                self.elided = 1;
+                marker
+            }
+        };
+
+        // Normally the oldest unpinned line after the marker goes; if there
+        // is none, e.g. lines were inserted above it until nothing follows,
+        // take the nearest one before.
+        let removed = (marker + 1..msgs.len())
+            .find(|&i| !msgs[i].pinned)
+            .or_else(|| (0..marker).rev().find(|&i| !msgs[i].pinned))
+            .unwrap_or(marker - 1);
+        if removed < marker {
+            self.marker = Some(marker - 1);
+        }
+        msgs.remove(removed);
+        self.elided += 1;
+
+        let marker = self.marker.unwrap();
+        msgs[marker] = LogEntry::new(format!("… {} lines elided …", self.elided));
+        removed
+    }
+}
+
+// UI state shared between the UI and every MessageLogger handle, so it can
+// be read and driven from FFI threads.
+struct ViewState {
+    // Messages between the bottom of the view and the tail.
+    scroll_offset: AtomicUsize,
+    // As of the last frame, for clamping scroll requests between frames.
+    page_height: AtomicUsize,
+    max_scroll: AtomicUsize,
+    // Mirror of the input cursor and length in chars, plus a pending move
+    // requested from outside (NO_CURSOR_REQUEST if none).
+    cursor: AtomicUsize,
+    input_len: AtomicUsize,
+    cursor_request: AtomicUsize,
+    // Bumped with the messages lock held whenever the buffer, or anything
+    // else that changes how it is drawn, changes.
+    revision: AtomicU64,
+}
+
+const NO_CURSOR_REQUEST: usize = usize::MAX;
+
+impl ViewState {
+    // Clamped to the last frame's bounds as the request is made, so scrolling
+    // past either end never leaves an offset that has to be unwound first.
+    fn scroll_by(&self, lines: isize) {
+        let max_scroll = self.max_scroll.load(Ordering::Relaxed);
+        let current = self.scroll_offset.load(Ordering::Relaxed).min(max_scroll);
+        let offset = if lines >= 0 {
+            current.saturating_add(lines as usize)
+        } else {
+            current.saturating_sub(lines.unsigned_abs())
+        };
+        self.scroll_offset.store(offset.min(max_scroll), Ordering::Relaxed);
+    }
+}
+
+impl Default for ViewState {
+    fn default() -> Self {
+        Self {
+            scroll_offset: AtomicUsize::new(0),
+            page_height: AtomicUsize::new(0),
+            max_scroll: AtomicUsize::new(0),
+            cursor: AtomicUsize::new(0),
+            input_len: AtomicUsize::new(0),
+            cursor_request: AtomicUsize::new(NO_CURSOR_REQUEST),
+            revision: AtomicU64::new(0),
+        }
+    }
+}
+
+// Running counters, readable without taking the messages lock.
+#[derive(Default)]
+struct LogStats {
+    // Id of the latest error line, 0 if none yet.
+    last_error: AtomicU64,
+    // Last LogEntry id handed out.
+    last_id: AtomicU64,
+}
+
+#[derive(Clone)]
+pub struct MessageLogger {
+    pub messages: Arc<Mutex<VecDeque<LogEntry>>>,
+    view: Arc<ViewState>,
+    // Always locked after `messages`, never on its own while storing.
+    trim: Arc<Mutex<TrimState>>,
+    subscribers: broadcast::Sender<LogEntry>,
+    // Locked after `messages` and `trim` when a line is stored.
+    log_file: Arc<Mutex<Option<LogFile>>>,
+    stderr_mirror: Arc<AtomicBool>,
+    // Started the first time the mirror is turned on.
+    stderr_writer: Arc<OnceLock<StderrMirror>>,
+    incoming_timestamp: Arc<Mutex<Option<String>>>,
+    placeholder: Arc<Mutex<String>>,
+    labels: Arc<Mutex<HashMap<String, Color>>>,
+    folds: Arc<Mutex<FoldState>>,
+    prompt_once_requests: Arc<Mutex<Vec<PromptOnce>>>,
+    stats: Arc<LogStats>,
+}
+
+impl MessageLogger {
+    pub fn log(&self, message: String) -> LogStatus {
+        self.store_lines(message, LineOptions::default())
+    }
+
+    /// Logs `message` in `style`, which replaces the color its prefix would
+    /// otherwise get.
+    pub fn log_styled(&self, message: String, style: Style) -> LogStatus {
+        self.store_lines(message, LineOptions { style: Some(style), ..LineOptions::default() })
+    }
+
+    /// Logs one line made of `segments`, each drawn in its own style. Unset
+    /// style fields fall back to the line's usual color, so a bold label
+    /// followed by plain detail is `[("id:", bold), (" 42", default)]`.
+    /// ANSI codes are dropped and newlines become spaces.
+    pub fn log_spans(&self, segments: Vec<(String, Style)>) -> LogStatus {
+        let mut text = String::new();
+        let mut styled = Vec::new();
+        for (segment, style) in segments {
+            let start = text.len();
+            text.push_str(&strip_ansi_codes(&segment).replace('\n', " "));
+            styled.push((start..text.len(), style));
+        }
+        self.store_lines(text, LineOptions { segments: styled, ..LineOptions::default() })
+    }
+
+    /// Logs `message` after a `[label]` prefix drawn in the color set with
+    /// `set_label_color`. A label naming a level, like `ERROR`, counts as
+    /// that level and defaults to its color; other labels default to light
+    /// magenta.
+    pub fn log_labeled(&self, label: &str, message: String) -> LogStatus {
+        self.store_lines(message, LineOptions { label: Some(label), ..LineOptions::default() })
+    }
+
+    pub fn set_label_color(&self, label: &str, color: Color) {
+        locks::lock(&self.labels, Rank::Leaf).unwrap().insert(label.to_string(), color);
+        self.changed();
+    }
+
+    /// Logs `message` with `level`'s prefix (none if None) and keeps its
+    /// lines when the buffer is full and older lines are evicted. They stay
+    /// until the buffer is cleared. At most `MAX_PINNED` lines are pinned at
+    /// once; past that, lines are logged normally and a warning is shown.
+    pub fn log_pinned(&self, level: Option<Level>, message: String) -> LogStatus {
+        let message = match level {
+            Some(level) => format!("{} {}", level.prefix(), message),
+            None => message,
+        };
+        self.store_lines(message, LineOptions { pinned: true, ..LineOptions::default() })
+    }
+
+    /// Logs `message` centered or right-aligned in the message pane, e.g.
+    /// for a header or a summary, so it stays in place when the pane is
+    /// resized. Wrapped rows are aligned the same way.
+    pub fn log_aligned(&self, message: String, alignment: Alignment) -> LogStatus {
+        self.store_lines(message, LineOptions { alignment, ..LineOptions::default() })
+    }
+
+    fn store_lines(&self, message: String, options: LineOptions) -> LogStatus {
+        let timestamp = locks::lock(&self.incoming_timestamp, Rank::Leaf).unwrap().clone();
+        let Ok(mut msgs) = locks::lock(&self.messages, Rank::Messages) else {
+            return LogStatus::Dropped;
+        };
+        let mut trim = locks::lock(&self.trim, Rank::Trim).unwrap();
+        let open_fold = locks::lock(&self.folds, Rank::Leaf).unwrap().open;
+        let mut pinned = if options.pinned { msgs.iter().filter(|e| e.pinned).count() } else { 0 };
+        let mut pin_refused = false;
+
+        let lines: Vec<&str> = if message.is_empty() || message == "\n" {
+            vec![""]
+        } else {
+            message.lines().collect()
+        };
+
+        // Split multi-line messages into separate entries
+        let mut status = LogStatus::Accepted;
+        for line in lines {
+            let cut = timestamp.as_deref().and_then(|format| timestamp_prefix(line, format)).unwrap_or(0);
+            let line = &line[cut..];
+            let index = msgs.len();
+            let mut entry = match options.label {
+                Some(label) => {
+                    let mut entry = self.entry(&format!("[{}] {}", label, line));
+                    entry.label = Some(label.to_string());
+                    entry
+                }
+                None => self.entry(line),
+            };
+            entry.style = options.style;
+            entry.segments = options
+                .segments
+                .iter()
+                .map(|(range, style)| (range.start.saturating_sub(cut)..range.end.saturating_sub(cut), *style))
+                .filter(|(range, _)| !range.is_empty())
+                .collect();
+            entry.alignment = options.alignment;
+            entry.fold = open_fold.map(FoldPart::Member);
+            if options.pinned {
+                entry.pinned = pinned < MAX_PINNED;
+                pin_refused |= !entry.pinned;
+            }
+            let (line_status, _) = trim.store(&mut msgs, index, entry.clone());
+            if line_status != LogStatus::Dropped {
+                pinned += entry.pinned as usize;
+                self.publish(entry);
+            }
+            if line_status != LogStatus::Accepted {
+                status = line_status;
+            }
+        }
+        self.changed();
+
+        let warn = pin_refused && !std::mem::replace(&mut trim.pin_limit_warned, true);
+        drop(trim);
+        drop(msgs);
+        if warn {
+            self.warning(&format!("More than {} pinned lines; further lines are not pinned", MAX_PINNED));
+        }
+        status
+    }
+
+    /// Logs `message` as a single line (embedded newlines become spaces)
+    /// that can later be changed with `update`. Returns its id, or None if
+    /// it wasn't stored.
+    pub fn log_updatable(&self, message: &str) -> Option<u64> {
+        let message = message.replace('\n', " ");
+        let cut = self.incoming_timestamp_len(&message);
+        let mut msgs = locks::lock(&self.messages, Rank::Messages).ok()?;
+        let mut trim = locks::lock(&self.trim, Rank::Trim).unwrap();
+        let mut entry = self.entry(&message[cut..]);
+        entry.fold = locks::lock(&self.folds, Rank::Leaf).unwrap().open.map(FoldPart::Member);
+        let index = msgs.len();
+        let (status, _) = trim.store(&mut msgs, index, entry.clone());
+        if status == LogStatus::Dropped {
+            return None;
+        }
+        self.changed();
+        let id = entry.id;
+        self.publish(entry);
+        Some(id)
+    }
+
+    /// Replaces the text of the line with `id` where it stands, e.g. to
+    /// redraw a progress bar, keeping its timestamp, style, label and the
+    /// rest. The number of lines doesn't change, so the scroll position is
+    /// unaffected; updates aren't published to subscribers or the log file.
+    /// Returns false if the line is gone.
+    pub fn update(&self, id: u64, message: &str) -> bool {
+        let message = message.replace('\n', " ");
+        let cut = self.incoming_timestamp_len(&message);
+        let mut msgs = locks::lock(&self.messages, Rank::Messages).unwrap();
+        let Some(entry) = msgs.iter_mut().rev().find(|e| e.id == id) else {
+            return false;
+        };
+        let updated = LogEntry::new(&message[cut..]);
+        entry.level = updated.level;
+        entry.tag = updated.tag;
+        // Styled ranges that no longer fit the new text would slice it
+        // out of bounds.
+        entry.segments.retain(|(range, _)| updated.text.get(range.clone()).is_some());
+        entry.text = updated.text;
+        self.changed();
+        true
+    }
+
+    /// Receives every line as it is stored. A subscriber that falls more than
+    /// `SUBSCRIBER_CAPACITY` lines behind gets `RecvError::Lagged` and skips
+    /// ahead, per `tokio::sync::broadcast` semantics.
+    pub fn subscribe(&self) -> broadcast::Receiver<LogEntry> {
+        self.subscribers.subscribe()
+    }
+
+    // Length of the `set_strip_incoming_timestamp` prefix at the start of
+    // `line`, or 0 if it has none.
+    fn incoming_timestamp_len(&self, line: &str) -> usize {
+        let format = locks::lock(&self.incoming_timestamp, Rank::Leaf).unwrap();
+        format.as_deref().and_then(|format| timestamp_prefix(line, format)).unwrap_or(0)
+    }
+
+    fn entry(&self, line: &str) -> LogEntry {
+        let mut entry = LogEntry::new(line);
+        entry.id = self.stats.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        entry
+    }
+
+    fn publish(&self, entry: LogEntry) {
+        if entry.is_error() {
+            self.stats.last_error.store(entry.id, Ordering::Relaxed);
+        }
+
+        if let Some(file) = locks::lock(&self.log_file, Rank::LogFile).unwrap().as_ref() {
+            file.write(&strip_ansi_codes(&entry.text));
+        }
+        // Callers still hold the message and trim locks, so a blocked
+        // stderr is left to the mirror's own thread.
+        if let Some(writer) = self.stderr_writer.get() {
+            if self.stderr_mirror.load(Ordering::Relaxed) {
+                writer.write(&strip_ansi_codes(&entry.text));
+            }
+        }
+
+        // Sending only fails when nobody is subscribed.
+        let _ = self.subscribers.send(entry);
+    }
+
+    /// Also writes every stored line, without ANSI codes, to stderr, e.g.
+    /// to grep a session with `2>session.log`. Only while stderr is
+    /// redirected: on the terminal the lines would land in the middle of
+    /// the UI. Returns whether the mirror is on.
+    pub fn set_stderr_mirror(&self, enabled: bool) -> bool {
+        let mut enabled = enabled && !io::stderr().is_terminal();
+        if enabled && self.stderr_writer.get().is_none() {
+            match StderrMirror::start() {
+                // Another logger may have started one first.
+                Ok(writer) => _ = self.stderr_writer.set(writer),
+                Err(_) => enabled = false,
+            }
+        }
+        self.stderr_mirror.store(enabled, Ordering::Relaxed);
+        enabled
+    }
+
+    /// Appends every stored line, without ANSI codes, to `path`, rotating
+    /// the file when it would exceed `max_size` bytes. Replaces any log file
+    /// set before.
+    pub fn set_log_file(&self, path: &Path, max_size: u64) -> io::Result<()> {
+        let file = LogFile::open(path, max_size)?;
+        *locks::lock(&self.log_file, Rank::LogFile).unwrap() = Some(file);
+        Ok(())
+    }
+
+    pub fn close_log_file(&self) {
+        *locks::lock(&self.log_file, Rank::LogFile).unwrap() = None;
+    }
+
+    /// Drops a timestamp the sender already put at the start of each
+    /// line, e.g. `Some("[%H:%M:%S]")`, so lines aren't timestamped twice;
+    /// see `text::timestamp_prefix` for the format. Lines that don't start
+    /// with a match, including ones starting with an ANSI code, are kept
+    /// as they are. None turns it off.
+    pub fn set_strip_incoming_timestamp(&self, format: Option<&str>) {
+        *locks::lock(&self.incoming_timestamp, Rank::Leaf).unwrap() = format.map(str::to_string);
+    }
+
+    pub fn set_placeholder(&self, text: &str) {
+        *locks::lock(&self.placeholder, Rank::Leaf).unwrap() = text.to_string();
+    }
+
+    /// Shows `prompt` in place of the usual one for the next line, which
+    /// goes to `answer` instead of the command handler; then the usual
+    /// prompt is back. `answer` gets `None` if the question is cancelled:
+    /// by Escape, by another `prompt_once`, or by the UI exiting. The line
+    /// is recorded in history only if `remember` is set. `answer` runs on
+    /// the UI thread.
+    pub fn prompt_once<F>(&self, prompt: &str, remember: bool, answer: F)
+    where
+        F: FnOnce(Option<String>) + Send + 'static,
+    {
+        let question = PromptOnce { prompt: prompt.to_string(), remember, answer: Box::new(answer) };
+        locks::lock(&self.prompt_once_requests, Rank::Leaf).unwrap().push(question);
+    }
+
+    /// Inserts `message` before the entry at `index`, clamped to the ends of
+    /// the buffer. A scrolled-up view keeps showing the same lines. Lines
+    /// inserted between two lines of a folded block join the block; the
+    /// block open for `log` is not involved.
+    pub fn insert_at(&self, index: usize, message: String) {
+        let mut msgs = locks::lock(&self.messages, Rank::Messages).unwrap();
+        let mut trim = locks::lock(&self.trim, Rank::Trim).unwrap();
+        let mut index = index.min(msgs.len());
+
+        let lines: Vec<&str> = if message.is_empty() {
+            vec![""]
+        } else {
+            message.lines().collect()
+        };
+
+        // The scroll offset is left alone: the UI's scroll anchor keeps a
+        // scrolled-up view on the same entry when lines land below it.
+        for line in lines {
+            let mut entry = self.entry(line);
+            let fold_of = |entry: &LogEntry| match entry.fold {
+                Some(FoldPart::Header) => Some(entry.id),
+                Some(FoldPart::Member(id)) => Some(id),
+                None => None,
+            };
+            let before = index.checked_sub(1).and_then(|i| fold_of(&msgs[i]));
+            let after = msgs.get(index).filter(|e| e.fold != Some(FoldPart::Header)).and_then(fold_of);
+            entry.fold = before.filter(|&id| Some(id) == after).map(FoldPart::Member);
+            let (status, stored_at) = trim.store(&mut msgs, index, entry.clone());
+            if status != LogStatus::Dropped {
+                self.publish(entry);
+            }
+            index = stored_at + 1;
+        }
+        self.changed();
+    }
+
+    /// Scrolls by `lines` messages: positive moves up into history, negative
+    /// toward the tail. Clamped to the bounds of the last rendered frame.
+    pub fn scroll_by(&self, lines: isize) {
+        self.view.scroll_by(lines);
+    }
+
+    /// Input cursor position, in chars (not bytes).
+    pub fn cursor(&self) -> usize {
+        self.view.cursor.load(Ordering::Relaxed)
+    }
+
+    /// Moves the input cursor to `pos` chars, clamped to the input length.
+    /// Takes effect on the UI's next tick.
+    pub fn set_cursor(&self, pos: usize) {
+        let pos = pos.min(self.view.input_len.load(Ordering::Relaxed));
+        self.view.cursor.store(pos, Ordering::Relaxed);
+        self.view.cursor_request.store(pos, Ordering::Relaxed);
+    }
+
+    pub fn scroll_to_top(&self) {
+        let max_scroll = self.view.max_scroll.load(Ordering::Relaxed);
+        self.view.scroll_offset.store(max_scroll, Ordering::Relaxed);
+    }
+
+    pub fn scroll_to_bottom(&self) {
+        self.view.scroll_offset.store(0, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        let mut msgs = locks::lock(&self.messages, Rank::Messages).unwrap();
+        msgs.clear();
+        locks::lock(&self.trim, Rank::Trim).unwrap().reset();
+        *locks::lock(&self.folds, Rank::Leaf).unwrap() = FoldState::default();
+        self.changed();
+    }
+
+    /// Removes and returns every buffered entry, oldest first, under a
+    /// single lock so no line is lost or returned twice.
+    pub fn drain(&self) -> Vec<LogEntry> {
+        let mut msgs = locks::lock(&self.messages, Rank::Messages).unwrap();
+        locks::lock(&self.trim, Rank::Trim).unwrap().reset();
+        *locks::lock(&self.folds, Rank::Leaf).unwrap() = FoldState::default();
+        self.view.scroll_offset.store(0, Ordering::Relaxed);
+        self.changed();
+        msgs.drain(..).collect()
+    }
+
+    /// Starts a block that is shown collapsed, as "▸ label (N lines)", until
+    /// expanded. Lines logged until `fold_end` go into it; starting another
+    /// block ends this one. Returns the fold's id, or None if the header
+    /// wasn't stored.
+    pub fn fold_begin(&self, label: &str) -> Option<u64> {
+        let mut msgs = locks::lock(&self.messages, Rank::Messages).ok()?;
+        let mut trim = locks::lock(&self.trim, Rank::Trim).unwrap();
+        let mut entry = self.entry(&label.replace('\n', " "));
+        entry.fold = Some(FoldPart::Header);
+        let index = msgs.len();
+        let (status, _) = trim.store(&mut msgs, index, entry.clone());
+        let id = (status != LogStatus::Dropped).then_some(entry.id);
+        locks::lock(&self.folds, Rank::Leaf).unwrap().open = id;
+        if id.is_some() {
+            self.publish(entry);
+        }
+        self.changed();
+        id
+    }
+
+    pub fn fold_end(&self) {
+        locks::lock(&self.folds, Rank::Leaf).unwrap().open = None;
+    }
+
+    pub fn set_fold_expanded(&self, id: u64, expanded: bool) {
+        let _msgs = locks::lock(&self.messages, Rank::Messages).unwrap();
+        let mut folds = locks::lock(&self.folds, Rank::Leaf).unwrap();
+        if expanded {
+            folds.expanded.insert(id);
+        } else {
+            folds.expanded.remove(&id);
+        }
+        self.changed();
+    }
+
+    /// Expands every fold in the buffer, or collapses them all if they are
+    /// all expanded already.
+    pub fn toggle_folds(&self) {
+        let msgs = locks::lock(&self.messages, Rank::Messages).unwrap();
+        let headers: Vec<u64> = msgs.iter().filter(|e| e.fold == Some(FoldPart::Header)).map(|e| e.id).collect();
+        let mut folds = locks::lock(&self.folds, Rank::Leaf).unwrap();
+        if headers.iter().all(|id| folds.expanded.contains(id)) {
+            folds.expanded.clear();
+        } else {
+            folds.expanded.extend(headers);
+        }
+        self.changed();
+    }
+
+    // Invalidates the UI's cached message rows.
+    fn changed(&self) {
+        self.view.revision.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_trim_strategy(&self, strategy: TrimStrategy) {
+        let _msgs = locks::lock(&self.messages, Rank::Messages).unwrap();
+        let mut trim = locks::lock(&self.trim, Rank::Trim).unwrap();
+        trim.strategy = strategy;
+        // An existing marker stays in the buffer as a plain line.
+        trim.reset();
+    }
+
+    pub fn info(&self, message: &str) -> LogStatus {
+        self.log(format!("[INFO] {}", message))
+    }
+
+    pub fn error(&self, message: &str) -> LogStatus {
+        self.log(format!("[ERROR] {}", message))
+    }
+
+    pub fn success(&self, message: &str) -> LogStatus {
+        self.log(format!("[SUCCESS] {}", message))
+    }
+
+    pub fn warning(&self, message: &str) -> LogStatus {
+        self.log(format!("[WARNING] {}", message))
+    }
+
+    pub fn debug(&self, message: &str) -> LogStatus {
+        self.log(format!("[DEBUG] {}", message))
+    }
+
+    /// Logs a set of sample lines for checking what the terminal renders:
+    /// every level, the 16 ANSI colors, the 256-color grays, a truecolor
+    /// gradient, wide and combining characters, a line long enough to
+    /// wrap, alignment, and enough numbered lines to scroll. Meant for
+    /// compatibility checks and bug reports.
+    pub fn run_diagnostics(&self) {
+        let swatch = |color: Color| ("  ".to_string(), Style::default().bg(color));
+        self.log_aligned("── Diagnostics ──".to_string(), Alignment::Center);
+        self.info("Info line");
+        self.success("Success line");
+        self.warning("Warning line");
+        self.error("Error line");
+        self.debug("Debug line");
+
+        let mut ansi = vec![("ANSI:  ".to_string(), Style::default())];
+        ansi.extend(ANSI_COLORS.into_iter().map(swatch));
+        self.log_spans(ansi);
+        let mut grays = vec![("Grays: ".to_string(), Style::default())];
+        grays.extend((232..=255).map(|i| swatch(Color::Indexed(i))));
+        self.log_spans(grays);
+        let mut gradient = vec![("RGB:   ".to_string(), Style::default())];
+        gradient.extend((0..32u8).map(|i| swatch(Color::Rgb(i * 8, 128, 255 - i * 8))));
+        self.log_spans(gradient);
+        self.log("\x1b[1mBold\x1b[0m \x1b[3mItalic\x1b[0m \x1b[4mUnderlined\x1b[0m \x1b[31mANSI red\x1b[0m".to_string());
+
+        self.log("Wide: 日本語 한국어 中文 | Emoji: 🦀 👨\u{200d}👩\u{200d}👧 | Combining: e\u{301} n\u{303}".to_string());
+        self.log("Tabs:\tone\ttwo\tthree".to_string());
+        self.log(format!("Wrap: {}", "the quick brown fox jumps over the lazy dog ".repeat(6).trim_end()));
+        self.log_aligned("Right-aligned line".to_string(), Alignment::Right);
+
+        for i in 1..=DIAGNOSTIC_SCROLL_LINES {
+            self.log(format!("Scroll {:>2}/{}", i, DIAGNOSTIC_SCROLL_LINES));
+        }
+        self.log_aligned("── End of diagnostics ──".to_string(), Alignment::Center);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strip_ansi_codes_removes_complete_sequences() {
+        assert_eq!(strip_ansi_codes("\x1b[31mred\x1b[0m text"), "red text");
+    }
+
+    fn render(raw: &str) -> (String, Color) {
+        let (text, style) = entry_parts(&LogEntry::new(raw));
+        (text, style.fg.unwrap())
+    }
+
+    #[test]
+    fn pipeline_colors_plain_level_prefix() {
+        assert_eq!(render("[INFO] hi"), ("[INFO] hi".to_string(), Color::Cyan));
+    }
+
+    #[test]
+    fn pipeline_strips_codes_before_matching_prefix() {
+        assert_eq!(
+            render("\x1b[31m[ERROR]\x1b[0m disk full"),
+            ("[ERROR] disk full".to_string(), Color::Red)
+        );
+        assert_eq!(render("\x1b[1mplain\x1b[0m"), ("plain".to_string(), Color::White));
+    }
+
+    #[test]
+    fn pipeline_removes_banner_tag() {
+        assert_eq!(
+            render("[RUST3] ██████╔╝"),
+            (" ██████╔╝".to_string(), Color::Rgb(255, 136, 0))
+        );
+    }
+
+    fn logger_with_lines(count: usize) -> MessageLogger {
+        let logger = TerminalUI::new().get_message_logger();
+        for i in 0..count {
+            logger.log(format!("line {}", i));
+        }
+        logger
+    }
+
+    fn texts(logger: &MessageLogger) -> Vec<String> {
+        logger.messages.lock().unwrap().iter().map(|e| e.text.clone()).collect()
+    }
+
+    #[test]
+    fn log_below_cap_keeps_everything() {
+        let logger = logger_with_lines(MAX_MESSAGES - 1);
+        let texts = texts(&logger);
+        assert_eq!(texts.len(), MAX_MESSAGES - 1);
+        assert_eq!(texts[0], "line 0");
+        assert_eq!(logger.log("one more".to_string()), LogStatus::Accepted);
+        assert_eq!(logger.messages.lock().unwrap().len(), MAX_MESSAGES);
+    }
+
+    #[test]
+    fn log_at_cap_evicts_oldest() {
+        let logger = logger_with_lines(MAX_MESSAGES);
+        assert_eq!(texts(&logger)[0], "line 0");
+
+        assert_eq!(logger.log("newest".to_string()), LogStatus::Evicted);
+        let texts = texts(&logger);
+        assert_eq!(texts.len(), MAX_MESSAGES);
+        assert_eq!(texts[0], "line 1");
+        assert_eq!(texts.last().unwrap(), "newest");
+    }
+
+    #[test]
+    fn log_well_above_cap_keeps_newest_in_order() {
+        let total = MAX_MESSAGES * 2 + 5;
+        let texts = texts(&logger_with_lines(total));
+        assert_eq!(texts.len(), MAX_MESSAGES);
+        let expected: Vec<String> = (total - MAX_MESSAGES..total).map(|i| format!("line {}", i)).collect();
+        assert_eq!(texts, expected);
+    }
+
+    #[test]
+    fn multi_line_message_across_cap_evicts_per_line() {
+        let logger = logger_with_lines(MAX_MESSAGES - 1);
+        assert_eq!(logger.log("a\nb\nc".to_string()), LogStatus::Evicted);
+        let texts = texts(&logger);
+        assert_eq!(texts.len(), MAX_MESSAGES);
+        assert_eq!(texts[0], "line 2");
+        assert_eq!(&texts[MAX_MESSAGES - 3..], ["a", "b", "c"]);
+    }
+
+    fn row_text(row: &Line) -> String {
+        row.spans.iter().map(|s| s.content.as_ref()).collect()
+    }
+
+    #[test]
+    fn level_badges_replace_prefix_and_align_text() {
+        let mut ui = TerminalUI::new();
+        ui.set_level_badges(true);
+
+        let rows = ui.entry_rows(&LogEntry::new("[WARNING] low disk"), None);
+        assert_eq!(row_text(&rows[0]), "[!] low disk");
+        assert_eq!(rows[0].spans[0].style.fg, Some(Color::Yellow));
+        assert_eq!(rows[0].spans[1].style.fg, Some(Color::White));
+
+        let rows = ui.entry_rows(&LogEntry::new("plain"), None);
+        assert_eq!(row_text(&rows[0]), "    plain");
+
+        let rows = ui.entry_rows(&LogEntry::new("✗ failed to connect"), Some(13));
+        let texts: Vec<String> = rows.iter().map(row_text).collect();
+        assert_eq!(texts, ["[✗] failed to", "    connect"]);
+    }
+
+    #[test]
+    fn fit_title_drops_lowest_priority_segments_first() {
+        let segments = || vec![
+            TitleSegment::new("R-Term", u8::MAX),
+            TitleSegment::new("(↑12)", 2),
+            TitleSegment::new("[filter]", 1),
+        ];
+        assert_eq!(fit_title(segments(), 40), "R-Term (↑12) [filter]");
+        assert_eq!(fit_title(segments(), 14), "R-Term (↑12)");
+        assert_eq!(fit_title(segments(), 8), "R-Term");
+        // The last segment is kept even when nothing fits.
+        assert_eq!(fit_title(segments(), 2), "R-Term");
+    }
+
+    #[test]
+    fn history_navigation_with_no_entries_is_a_no_op() {
+        let mut ui = TerminalUI::new();
+        ui.input = "draft".to_string();
+        ui.cursor_position = 2;
+
+        ui.navigate_history(HistoryStep::Older);
+        ui.navigate_history(HistoryStep::Newer);
+        assert_eq!(ui.input, "draft");
+        assert_eq!(ui.cursor_position, 2);
+        assert_eq!(ui.history_index, 0);
+    }
+
+    #[test]
+    fn history_navigation_with_one_entry() {
+        let mut ui = TerminalUI::new();
+        ui.history.push("status".to_string());
+        ui.history_index = 1;
+
+        ui.navigate_history(HistoryStep::Older);
+        assert_eq!(ui.input, "status");
+        assert_eq!(ui.history_index, 0);
+
+        // Already at the oldest entry.
+        ui.navigate_history(HistoryStep::Older);
+        assert_eq!(ui.input, "status");
+        assert_eq!(ui.history_index, 0);
+
+        ui.navigate_history(HistoryStep::Newer);
+        assert_eq!(ui.input, "");
+        assert_eq!(ui.history_index, 1);
+
+        ui.navigate_history(HistoryStep::Newer);
+        assert_eq!(ui.history_index, 1);
+    }
+
+    #[test]
+    fn history_navigation_recovers_from_stale_index() {
+        let mut ui = TerminalUI::new();
+        ui.history.push("status".to_string());
+        ui.history_index = 7;
+
+        ui.navigate_history(HistoryStep::Older);
+        assert_eq!(ui.input, "status");
+        assert_eq!(ui.history_index, 0);
+    }
+
+    #[test]
+    fn sgr_reset_returns_to_the_line_style() {
+        let (text, runs) = sgr_runs("\x1b[31mred\x1b[0m plain");
+        assert_eq!(text, "red plain");
+        assert_eq!(runs, vec![(0..3, Style::default().fg(Color::Red))]);
+
+        let (_, runs) = sgr_runs("\x1b[1;38;5;208mhot\x1b[39m bold\x1b[22m done");
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        assert_eq!(runs, vec![(0..3, bold.fg(Color::Indexed(208))), (3..8, bold)]);
+    }
+
+    #[test]
+    fn unknown_sequences_leave_the_style_alone() {
+        let (text, runs) = sgr_runs("\x1b[4;44mlink\x1b[2K\x1b[53;38;5m tail\x1b[m");
+        assert_eq!(text, "link tail");
+        let style = Style::default().bg(Color::Blue).add_modifier(Modifier::UNDERLINED);
+        assert_eq!(runs, vec![(0..9, style)]);
+    }
+
+    #[test]
+    fn timestamp_format_is_checked_when_set() {
+        let mut ui = TerminalUI::new();
+        let error = ui.set_timestamp_format("%H:%M %Z").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(ui.timestamp_format, DEFAULT_TIMESTAMP_FORMAT);
+        ui.set_timestamp_format("%I:%M %p").unwrap();
+
+        ui.set_timestamp_format("%Y-%m-%d %H:%M:%S.%f").unwrap();
+        let time = SystemTime::UNIX_EPOCH + Duration::from_millis(1_709_968_000_042);
+        // The rest depends on the local time zone.
+        assert!(clock_time(time, &ui.timestamp_format).ends_with(":40.042"));
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(19_723), (2024, 1, 1));
+        assert_eq!(civil_date(19_782), (2024, 2, 29));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn timestamps_lead_every_line_in_gray() {
+        let mut ui = TerminalUI::new();
+        ui.set_timestamps(true);
+        let logger = ui.get_message_logger();
+        logger.info("first\nsecond");
+
+        let messages = logger.messages.lock().unwrap();
+        for entry in messages.iter() {
+            let line = &ui.entry_rows(entry, None)[0];
+            assert_eq!(line.spans[0].content, format!("{} ", clock_time(entry.timestamp, DEFAULT_TIMESTAMP_FORMAT)));
+            assert_eq!(line.spans[0].style, TIMESTAMP_STYLE);
+        }
+    }
+
+    #[test]
+    fn wrapped_rows_are_indented_by_the_timestamp_width() {
+        let mut ui = TerminalUI::new();
+        ui.set_timestamps(true);
+        ui.set_timestamp_format("%H時%M分").unwrap();
+        let logger = ui.get_message_logger();
+        logger.info("aaaa bbbb cccc dddd");
+        let entry = logger.messages.lock().unwrap()[0].clone();
+
+        let time = clock_time(entry.timestamp, &ui.timestamp_format);
+        let rows = ui.entry_rows(&entry, Some(display_width(&time) + 1 + 14));
+        assert!(rows.len() > 1);
+        assert_eq!(rows[1].spans[0].content, " ".repeat(display_width(&time) + 1));
+    }
+
+    #[test]
+    fn preserved_colors_end_at_a_reset() {
+        let mut ui = TerminalUI::new();
+        ui.set_preserve_colors(true);
+        let logger = ui.get_message_logger();
+        logger.log("\x1b[31mred\x1b[0m plain".to_string());
+        let entry = logger.messages.lock().unwrap()[0].clone();
+
+        let line = &ui.entry_rows(&entry, None)[0];
+        let plain = entry_parts(&entry).1;
+        assert_eq!(line.spans[0].content, "red");
+        assert_eq!(line.spans[0].style, plain.fg(Color::Red));
+        assert_eq!(line.spans[1].content, " plain");
+        assert_eq!(line.spans[1].style, plain);
+    }
+
+    #[test]
+    fn spans_are_drawn_in_their_own_styles() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        logger.log_spans(vec![("id:".to_string(), bold), (" 42".to_string(), Style::default())]);
+        let entry = logger.messages.lock().unwrap()[0].clone();
+        assert_eq!(entry.text, "id: 42");
+
+        let line = &ui.entry_rows(&entry, None)[0];
+        let plain = entry_parts(&entry).1;
+        assert_eq!(line.spans[0].content, "id:");
+        assert_eq!(line.spans[0].style, plain.add_modifier(Modifier::BOLD));
+        assert_eq!(line.spans[1].content, " 42");
+        assert_eq!(line.spans[1].style, plain);
+    }
+
+    #[test]
+    fn strip_ansi_codes_drops_truncated_sequence_at_end() {
+        assert_eq!(strip_ansi_codes("loading\x1b[3"), "loading");
+        assert_eq!(strip_ansi_codes("loading\x1b[31"), "loading");
+        assert_eq!(strip_ansi_codes("\x1b["), "");
+    }
+
+    #[test]
+    fn strip_ansi_codes_keeps_text_after_malformed_sequence() {
+        assert_eq!(strip_ansi_codes("a\x1b[3é rest"), "a[3é rest");
+
+        let long = format!("x\x1b[{}m tail", "1;".repeat(MAX_CSI_LEN));
+        assert!(strip_ansi_codes(&long).ends_with("m tail"));
+    }
+
+    #[test]
+    fn completion_list_selection_is_accepted_with_enter() {
+        let mut ui = TerminalUI::new();
+        ui.set_completion_behavior(CompletionBehavior::ListOnly);
+        ui.complete(vec![Completion::new("/help", "Show help"), "/history".into()]);
+        assert_eq!(ui.completion_list.len(), 2);
+
+        assert!(ui.handle_completion_list_key(KeyEvent::from(KeyCode::Up)));
+        assert_eq!(ui.completion_selection, 1);
+        assert!(ui.handle_completion_list_key(KeyEvent::from(KeyCode::Enter)));
+        assert_eq!(ui.input, "/history");
+        assert!(ui.completion_list.is_empty());
+    }
+
+    #[test]
+    fn scrolling_stops_at_the_top() {
+        let view = ViewState::default();
+        view.max_scroll.store(7, Ordering::Relaxed);
+
+        for _ in 0..4 {
+            view.scroll_by(5);
+        }
+        assert_eq!(view.scroll_offset.load(Ordering::Relaxed), 7);
+
+        view.scroll_by(-5);
+        assert_eq!(view.scroll_offset.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn auto_link_styles_urls_across_wrapped_rows() {
+        let mut ui = TerminalUI::new();
+        ui.set_auto_link(true);
+        let rows = ui.entry_rows(&LogEntry::new("see https://example.org/abc now"), Some(16));
+
+        let linked: Vec<&str> = rows
+            .iter()
+            .flat_map(|row| row.spans.iter())
+            .filter(|span| span.style.add_modifier.contains(Modifier::UNDERLINED))
+            .map(|span| span.content.as_ref())
+            .collect();
+        assert_eq!(linked, vec!["https://", "example.org/abc"]);
+    }
+
+    #[test]
+    fn labeled_lines_color_only_the_label() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        logger.set_label_color("net", Color::Green);
+        logger.log_labeled("net", "connected".to_string());
+        logger.log_labeled("ERROR", "lost".to_string());
+
+        let msgs = logger.messages.lock().unwrap();
+        let rows = ui.entry_rows(&msgs[0], None);
+        assert_eq!(rows[0].spans[0].content, "[net]");
+        assert_eq!(rows[0].spans[0].style.fg, Some(Color::Green));
+        assert_eq!(rows[0].spans[1].content, " connected");
+
+        assert_eq!(msgs[1].level, Some(Level::Error));
+        assert_eq!(ui.entry_rows(&msgs[1], None)[0].spans[0].style.fg, Some(Color::Red));
+    }
+
+    fn screen(ui: &TerminalUI) -> String {
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        terminal.draw(|f| ui.draw(f)).unwrap();
+        terminal.backend().buffer().content.iter().map(|cell| cell.symbol()).collect()
+    }
+
+    #[test]
+    fn cached_rows_are_redrawn_after_a_change() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        logger.log("first".to_string());
+        assert!(screen(&ui).contains("first"));
+
+        logger.log("second".to_string());
+        assert!(screen(&ui).contains("second"));
+
+        let id = logger.log_updatable("progress 1").unwrap();
+        assert!(screen(&ui).contains("progress 1"));
+        logger.update(id, "progress 2");
+        assert!(screen(&ui).contains("progress 2"));
+    }
+
+    #[test]
+    fn updated_lines_keep_everything_but_the_text() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        let id = logger.log_updatable("[INFO] step 1").unwrap();
+        let before = {
+            let mut messages = logger.messages.lock().unwrap();
+            let entry = messages.iter_mut().find(|e| e.id == id).unwrap();
+            entry.timestamp = SystemTime::UNIX_EPOCH;
+            entry.label = Some("job".to_string());
+            entry.pinned = true;
+            entry.alignment = Alignment::Right;
+            entry.segments = vec![(0..6, Style::default().fg(Color::Green)), (7..13, Style::default())];
+            entry.clone()
+        };
+
+        assert!(logger.update(id, "[ERROR] step"));
+        let after = logger.messages.lock().unwrap().iter().find(|e| e.id == id).unwrap().clone();
+        assert_eq!(after.text, "[ERROR] step");
+        assert_eq!(after.level, Some(Level::Error));
+        assert_eq!(after.timestamp, before.timestamp);
+        assert_eq!(after.label, before.label);
+        assert!(after.pinned);
+        assert_eq!(after.alignment, Alignment::Right);
+        assert_eq!(after.segments, before.segments[..1]);
+    }
+
+    #[test]
+    fn spinner_stops_after_output_goes_quiet() {
+        assert!(spinner_frame(SystemTime::now()).is_some());
+        assert!(spinner_frame(SystemTime::now() - Duration::from_secs(1)).is_none());
+    }
+
+    #[derive(Default)]
+    struct MockHandler {
+        submitted: Vec<String>,
+    }
+
+    impl CommandHandler for MockHandler {
+        async fn on_command(&mut self, line: String) -> Result<bool, String> {
+            self.submitted.push(line);
+            Ok(false)
+        }
+
+        fn complete(&mut self, _buffer: &str, _cursor: usize) -> Vec<Completion> {
+            vec!["status".into()]
+        }
+    }
+
+    #[tokio::test]
+    async fn handler_receives_completed_and_submitted_lines() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        ui.handle_key(KeyEvent::from(KeyCode::Char('s')), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Tab), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Enter), &mut handler).await;
+
+        assert_eq!(handler.submitted, vec!["status"]);
+        assert_eq!(ui.history, vec!["status"]);
+    }
+
+    #[test]
+    fn shared_prefix_is_completed_before_full_candidates() {
+        let mut ui = TerminalUI::new();
+        ui.input = "g".to_string();
+        ui.cursor_position = 1;
+        ui.complete(vec!["gamemode".into(), "gamerule".into()]);
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("game", 4));
+        assert!(ui.completion_list.is_empty());
+
+        // Nothing more in common, so they are listed instead.
+        ui.complete(vec!["gamemode".into(), "gamerule".into()]);
+        assert_eq!(ui.input, "game");
+        assert_eq!(ui.completion_list.len(), 2);
+
+        ui.completion_list.clear();
+        ui.complete(vec!["gamerule".into()]);
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("gamerule", 8));
+    }
+
+    #[test]
+    fn diverging_candidates_keep_the_input_and_list() {
+        let mut ui = TerminalUI::new();
+        ui.input = "a".to_string();
+        ui.cursor_position = 1;
+        ui.complete(vec!["apple".into(), "banana".into(), "cherry".into()]);
+
+        assert_eq!(ui.input, "a");
+        assert_eq!(ui.cursor_position, 1);
+        assert_eq!(ui.completion_list.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn tab_cycles_through_candidates() {
+        struct Fruit;
+        impl CommandHandler for Fruit {
+            async fn on_command(&mut self, _line: String) -> Result<bool, String> {
+                Ok(false)
+            }
+
+            fn complete(&mut self, _buffer: &str, _cursor: usize) -> Vec<Completion> {
+                vec!["apple".into(), "banana".into(), "cherry".into()]
+            }
+        }
+
+        let mut ui = TerminalUI::new();
+        ui.set_completion_behavior(CompletionBehavior::Cycle);
+        let mut handler = Fruit;
+        let mut press = async |code| {
+            ui.handle_key(KeyEvent::from(code), &mut handler).await;
+            ui.input.clone()
+        };
+        assert_eq!(press(KeyCode::Char('x')).await, "x");
+        assert_eq!(press(KeyCode::Tab).await, "apple");
+        assert_eq!(press(KeyCode::Tab).await, "banana");
+        assert_eq!(press(KeyCode::BackTab).await, "apple");
+        assert_eq!(press(KeyCode::BackTab).await, "cherry");
+        // Typing ends the cycle, so Tab asks for candidates again.
+        assert_eq!(press(KeyCode::Char('s')).await, "cherrys");
+        assert_eq!(press(KeyCode::BackTab).await, "cherry");
+
+        // The candidates are listed while cycling, then the list goes.
+        assert!(screen(&ui).contains("Completions (3)"));
+        ui.handle_key(KeyEvent::from(KeyCode::Char('!')), &mut handler).await;
+        assert!(!screen(&ui).contains("Completions"));
+        assert!(ui.key_bindings().iter().any(|(key, _)| key == "Tab/Shift+Tab"));
+    }
+
+    // Plays back `events`, then reports nothing ready, forever.
+    struct ScriptedEvents(VecDeque<Event>);
+
+    impl EventSource for ScriptedEvents {
+        fn poll(&mut self, _timeout: Duration) -> io::Result<bool> {
+            Ok(!self.0.is_empty())
+        }
+
+        fn read(&mut self) -> io::Result<Event> {
+            self.0.pop_front().ok_or_else(|| io::Error::other("no more events"))
+        }
+    }
+
+    #[tokio::test]
+    async fn mouse_wheel_scrolls_the_messages() {
+        let wheel = |kind| Event::Mouse(MouseEvent { kind, column: 5, row: 5, modifiers: KeyModifiers::NONE });
+        let mut events = ScriptedEvents(VecDeque::from([
+            wheel(MouseEventKind::ScrollUp),
+            wheel(MouseEventKind::ScrollUp),
+            wheel(MouseEventKind::ScrollDown),
+            Event::Key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+        ]));
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        let mut ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        for i in 0..50 {
+            logger.log(format!("line {}", i));
+        }
+
+        ui.run_on(&mut terminal, &mut events, &mut MockHandler::default()).await.unwrap();
+        assert_eq!(ui.scroll_offset(), WHEEL_SCROLL_LINES as usize);
+    }
+
+    #[tokio::test]
+    async fn clicks_copy_words_and_lines() {
+        let click = |column, row| {
+            let kind = MouseEventKind::Down(MouseButton::Left);
+            Event::Mouse(MouseEvent { kind, column, row, modifiers: KeyModifiers::NONE })
+        };
+        let mut events = ScriptedEvents(VecDeque::from([
+            click(8, 1),
+            click(8, 1),
+            click(3, 2),
+            click(3, 2),
+            click(3, 2),
+            Event::Key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+        ]));
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        let mut ui = TerminalUI::new();
+        ui.set_click_select(true);
+        let copied = Arc::new(Mutex::new(Vec::new()));
+        let sink = copied.clone();
+        ui.set_copy_handler(move |text| sink.lock().unwrap().push(text.to_string()));
+        let logger = ui.get_message_logger();
+        logger.log("alpha beta".to_string());
+        logger.log("\x1b[31mgamma\x1b[0m delta".to_string());
+
+        ui.run_on(&mut terminal, &mut events, &mut MockHandler::default()).await.unwrap();
+        assert_eq!(*copied.lock().unwrap(), ["beta", "gamma", "gamma delta"]);
+    }
+
+    #[tokio::test]
+    async fn run_on_plays_scripted_events_end_to_end() {
+        let key = |code| Event::Key(KeyEvent::from(code));
+        let mut events = ScriptedEvents(VecDeque::from([
+            key(KeyCode::Char('s')),
+            key(KeyCode::Tab),
+            key(KeyCode::Enter),
+            Event::Key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+        ]));
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+
+        ui.run_on(&mut terminal, &mut events, &mut handler).await.unwrap();
+        assert_eq!(handler.submitted, vec!["status"]);
+    }
+
+    #[tokio::test]
+    async fn f2_hides_lines_below_the_level() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let logger = ui.get_message_logger();
+        logger.debug("d");
+        logger.info("i");
+        logger.log("plain".to_string());
+        logger.warning("w");
+        logger.error("e");
+
+        let texts = |ui: &TerminalUI| -> Vec<String> {
+            let msgs = ui.messages.lock().unwrap();
+            ui.visible_entries(&msgs).iter().map(|e| e.text.clone()).collect()
+        };
+        ui.handle_key(KeyEvent::from(KeyCode::F(2)), &mut handler).await;
+        assert_eq!(texts(&ui), ["[INFO] i", "plain", "[WARNING] w", "[ERROR] e"]);
+        ui.handle_key(KeyEvent::from(KeyCode::F(2)), &mut handler).await;
+        assert_eq!(texts(&ui), ["plain", "[WARNING] w", "[ERROR] e"]);
+        assert!(screen(&ui).contains("R-Term [WARNING]+"));
+
+        ui.handle_key(KeyEvent::from(KeyCode::F(2)), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::F(2)), &mut handler).await;
+        assert_eq!(texts(&ui).len(), 5);
+        assert!(!screen(&ui).contains("]+"));
+    }
+
+    #[test]
+    fn collapsed_fold_shows_only_its_header() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        logger.log("before".to_string());
+        let id = logger.fold_begin("trace").unwrap();
+        logger.log("frame 1\nframe 2".to_string());
+        logger.fold_end();
+        logger.log("after".to_string());
+
+        let texts = |ui: &TerminalUI| -> Vec<String> {
+            let msgs = ui.messages.lock().unwrap();
+            ui.visible_entries(&msgs).iter().map(|e| e.text.clone()).collect()
+        };
+        assert_eq!(texts(&ui), ["before", "▸ trace (2 lines)", "after"]);
+
+        logger.set_fold_expanded(id, true);
+        assert_eq!(texts(&ui), ["before", "▾ trace (2 lines)", "frame 1", "frame 2", "after"]);
+    }
+
+    #[tokio::test]
+    async fn f7_picks_a_fold_to_expand() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let logger = ui.get_message_logger();
+        let first = logger.fold_begin("one").unwrap();
+        logger.log("a".to_string());
+        let second = logger.fold_begin("two").unwrap();
+        logger.log("b".to_string());
+        logger.fold_end();
+        logger.log("tail".to_string());
+
+        ui.handle_key(KeyEvent::from(KeyCode::F(7)), &mut handler).await;
+        assert_eq!(ui.fold_selection, Some(second));
+        ui.handle_key(KeyEvent::from(KeyCode::Up), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Enter), &mut handler).await;
+        assert_eq!(ui.fold_selection, Some(first));
+        let texts: Vec<String> = {
+            let msgs = ui.messages.lock().unwrap();
+            ui.visible_entries(&msgs).iter().map(|e| e.text.clone()).collect()
+        };
+        assert_eq!(texts, ["▾ one (1 lines)", "a", "▸ two (1 lines)", "tail"]);
+        assert_eq!(ui.scroll_offset(), 3);
+        assert!(handler.submitted.is_empty());
+
+        ui.handle_key(KeyEvent::from(KeyCode::Esc), &mut handler).await;
+        assert_eq!(ui.fold_selection, None);
+    }
+
+    #[tokio::test]
+    async fn error_panel_jumps_by_shown_lines() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let logger = ui.get_message_logger();
+        logger.error("first failure");
+        logger.fold_begin("trace").unwrap();
+        logger.log("frame 1".to_string());
+        logger.error("inner failure");
+        logger.fold_end();
+        logger.log("after 1\nafter 2".to_string());
+        ui.set_error_panel(true);
+
+        ui.handle_key(KeyEvent::from(KeyCode::Enter), &mut handler).await;
+        assert_eq!(ui.scroll_offset(), 3);
+        // Inside the collapsed fold, the header stands in for the line.
+        ui.handle_key(KeyEvent::from(KeyCode::Down), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Enter), &mut handler).await;
+        assert_eq!(ui.scroll_offset(), 2);
+    }
+
+    #[test]
+    fn dropped_insertions_leave_the_scroll_offset_alone() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        logger.set_trim_strategy(TrimStrategy::KeepHead);
+        for i in 0..MAX_MESSAGES {
+            logger.log(format!("line {}", i));
+        }
+        ui.set_scroll_offset(5);
+
+        logger.insert_at(MAX_MESSAGES, "late".to_string());
+        assert_eq!(ui.scroll_offset(), 5);
+    }
+
+    #[test]
+    fn scrolled_view_stays_put_when_lines_are_inserted_below_it() {
+        let mut ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        for i in 0..20 {
+            logger.info(&format!("line {}", i));
+            logger.debug(&format!("detail {}", i));
+        }
+        let id = logger.fold_begin("trace").unwrap();
+        logger.log("frame 1\nframe 2\nframe 3".to_string());
+        logger.fold_end();
+        logger.set_fold_expanded(id, false);
+        ui.set_min_level(Some(Level::Info));
+        screen(&ui);
+        logger.scroll_by(4);
+        assert_eq!(bottom_row(&ui), "[INFO] line 16");
+
+        // Below the view: past the collapsed fold and among hidden lines.
+        logger.insert_at(44, "late".to_string());
+        logger.insert_at(38, "in between".to_string());
+        assert_eq!(bottom_row(&ui), "[INFO] line 16");
+        // Above it, nothing below the bottom row changes.
+        logger.insert_at(0, "early".to_string());
+        assert_eq!(bottom_row(&ui), "[INFO] line 16");
+    }
+
+    #[test]
+    fn inserted_lines_join_the_fold_around_them() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        let id = logger.fold_begin("trace").unwrap();
+        logger.log("frame 1\nframe 2".to_string());
+        logger.fold_end();
+        logger.insert_at(2, "frame 1.5".to_string());
+        logger.insert_at(4, "outside".to_string());
+
+        let msgs = ui.messages.lock().unwrap();
+        let folds: Vec<Option<FoldPart>> = msgs.iter().map(|e| e.fold).collect();
+        let member = Some(FoldPart::Member(id));
+        assert_eq!(folds, [Some(FoldPart::Header), member, member, member, None]);
+    }
+
+    #[tokio::test]
+    async fn empty_tab_lists_without_inserting() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        ui.handle_key(KeyEvent::from(KeyCode::Tab), &mut handler).await;
+        assert_eq!(ui.input, "");
+        assert_eq!(ui.completion_list, vec![Completion::from("status")]);
+
+        ui.completion_list.clear();
+        ui.set_empty_tab_behavior(EmptyTab::Ignore);
+        ui.handle_key(KeyEvent::from(KeyCode::Tab), &mut handler).await;
+        assert!(ui.completion_list.is_empty());
+    }
+
+    #[tokio::test]
+    async fn prompt_once_sends_one_line_to_its_callback() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let answers = Arc::new(Mutex::new(Vec::new()));
+        let ask = |ui: &mut TerminalUI, remember: bool| {
+            let answers = answers.clone();
+            ui.get_message_logger().prompt_once("Overwrite? ", remember, move |answer| {
+                answers.lock().unwrap().push(answer);
+            });
+            ui.take_prompt_once();
+        };
+
+        ask(&mut ui, false);
+        assert!(screen(&ui).contains("Overwrite? "));
+        ui.input = "yes".to_string();
+        ui.handle_key(KeyEvent::from(KeyCode::Enter), &mut handler).await;
+        assert_eq!(*answers.lock().unwrap(), [Some("yes".to_string())]);
+        assert!(handler.submitted.is_empty());
+        assert!(ui.history.is_empty());
+        assert!(!screen(&ui).contains("Overwrite? "));
+
+        ask(&mut ui, true);
+        ui.input = "no".to_string();
+        ui.handle_key(KeyEvent::from(KeyCode::Esc), &mut handler).await;
+        assert_eq!(answers.lock().unwrap().last(), Some(&None));
+        assert_eq!(ui.input, "");
+
+        ask(&mut ui, true);
+        ui.input = "no".to_string();
+        ui.handle_key(KeyEvent::from(KeyCode::Enter), &mut handler).await;
+        assert_eq!(ui.history, ["no"]);
+    }
+
+    #[tokio::test]
+    async fn quitting_follows_the_exit_config() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        assert!(matches!(ui.handle_key(ctrl('c'), &mut handler).await, KeyAction::Exit));
+
+        ui.set_exit_config(ExitConfig {
+            quit_keys: vec![(KeyCode::Char('d'), KeyModifiers::CONTROL)],
+            require_confirmation: true,
+            ctrl_c: CtrlC::Interrupt,
+        });
+        ui.input = "half-typed".to_string();
+        assert!(matches!(ui.handle_key(ctrl('c'), &mut handler).await, KeyAction::Continue));
+        assert_eq!(ui.input, "");
+
+        assert!(matches!(ui.handle_key(ctrl('d'), &mut handler).await, KeyAction::Continue));
+        assert!(screen(&ui).contains("press Ctrl+D again to quit"));
+        assert!(matches!(ui.handle_key(ctrl('d'), &mut handler).await, KeyAction::Exit));
+
+        // Any other key in between starts over.
+        ui.handle_key(ctrl('d'), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Char('x')), &mut handler).await;
+        assert!(matches!(ui.handle_key(ctrl('d'), &mut handler).await, KeyAction::Continue));
+    }
+
+    #[tokio::test]
+    async fn unbound_keys_are_named_in_the_input_title() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let ctrl_q = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL);
+        ui.handle_key(ctrl_q, &mut handler).await;
+        assert!(!screen(&ui).contains("unbound key"));
+
+        ui.set_unbound_key_hints(true);
+        ui.handle_key(ctrl_q, &mut handler).await;
+        assert!(screen(&ui).contains("unbound key: Ctrl+Q"));
+
+        ui.handle_key(KeyEvent::from(KeyCode::Char('a')), &mut handler).await;
+        assert!(!screen(&ui).contains("unbound key"));
+    }
+
+    #[test]
+    fn pinned_lines_survive_eviction() {
+        let logger = TerminalUI::new().get_message_logger();
+        logger.log_pinned(Some(Level::Info), "connected to db".to_string());
+        for i in 0..MAX_MESSAGES * 2 {
+            logger.log(format!("line {}", i));
+        }
+
+        let texts = texts(&logger);
+        assert_eq!(texts.len(), MAX_MESSAGES);
+        assert_eq!(texts[0], "[INFO] connected to db");
+        assert_eq!(texts[MAX_MESSAGES - 1], format!("line {}", MAX_MESSAGES * 2 - 1));
+    }
+
+    #[test]
+    fn pinning_past_the_limit_warns_once() {
+        let logger = TerminalUI::new().get_message_logger();
+        for i in 0..MAX_PINNED + 2 {
+            logger.log_pinned(None, format!("pin {}", i));
+        }
+
+        let msgs = logger.messages.lock().unwrap();
+        assert_eq!(msgs.iter().filter(|e| e.pinned).count(), MAX_PINNED);
+        assert_eq!(msgs.iter().filter(|e| e.level == Some(Level::Warning)).count(), 1);
+    }
+
+    #[test]
+    fn cursor_follows_wide_prompt() {
+        let mut ui = TerminalUI::new();
+        ui.set_prompt("❯❯ ".to_string());
+        ui.input = "ab".to_string();
+        ui.cursor_position = 2;
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        terminal.draw(|f| ui.draw(f)).unwrap();
+        // Border, three cells of prompt, two of input.
+        assert_eq!(terminal.get_cursor_position().unwrap().x, 1 + 3 + 2);
+    }
+
+    #[test]
+    fn separator_sits_between_prompt_and_input() {
+        let mut ui = TerminalUI::new();
+        ui.set_prompt_separator("│ ", Style::default().fg(Color::DarkGray));
+        ui.input = "ab".to_string();
+        ui.cursor_position = 2;
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        terminal.draw(|f| ui.draw(f)).unwrap();
+        let screen: String = terminal.backend().buffer().content.iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("> │ ab"));
+        // Border, prompt, separator, input.
+        assert_eq!(terminal.get_cursor_position().unwrap().x, 1 + 2 + 2 + 2);
+    }
+
+    #[tokio::test]
+    async fn edit_keys_can_be_moved_and_freed() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let unhandled = Arc::new(Mutex::new(Vec::new()));
+        let sink = unhandled.clone();
+        ui.set_unhandled_key_handler(move |key| {
+            sink.lock().unwrap().push(key);
+            true
+        });
+        ui.unbind_edit_key(KeyCode::Char('w'), KeyModifiers::CONTROL);
+        ui.bind_edit_key(KeyCode::Backspace, KeyModifiers::ALT, EditAction::CutWordBefore);
+        ui.input = "one two".to_string();
+        ui.cursor_position = ui.input.len();
+
+        let ctrl_w = KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL);
+        ui.handle_key(ctrl_w, &mut handler).await;
+        assert_eq!(*unhandled.lock().unwrap(), [ctrl_w]);
+        assert_eq!(ui.input, "one two");
+        ui.handle_key(KeyEvent::new(KeyCode::Backspace, KeyModifiers::ALT), &mut handler).await;
+        assert_eq!(ui.input, "one ");
+        assert!(ui.key_bindings().contains(&("Alt+Backspace".to_string(), "Cut the word before the cursor")));
+    }
+
+    #[tokio::test]
+    async fn ctrl_w_deletes_the_word_before_the_cursor() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let ctrl_w = KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL);
+        ui.handle_key(ctrl_w, &mut handler).await;
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("", 0));
+
+        ui.input = "git commit  -m".to_string();
+        ui.cursor_position = "git commit  ".len();
+        ui.handle_key(ctrl_w, &mut handler).await;
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("git -m", 4));
+        ui.handle_key(ctrl_w, &mut handler).await;
+        ui.handle_key(ctrl_w, &mut handler).await;
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("-m", 0));
+    }
+
+    #[tokio::test]
+    async fn killed_text_can_be_yanked_back() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        for c in ['k', 'u', 'y'] {
+            ui.handle_key(ctrl(c), &mut handler).await;
+        }
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("", 0));
+
+        ui.input = "echo hello world".to_string();
+        ui.cursor_position = "echo ".len();
+        ui.handle_key(ctrl('k'), &mut handler).await;
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("echo ", 5));
+        ui.handle_key(ctrl('y'), &mut handler).await;
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("echo hello world", 16));
+
+        ui.cursor_position = "echo ".len();
+        ui.handle_key(ctrl('u'), &mut handler).await;
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("hello world", 0));
+        // Killing nothing keeps what was killed before.
+        ui.handle_key(ctrl('u'), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::End), &mut handler).await;
+        ui.handle_key(ctrl('y'), &mut handler).await;
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("hello worldecho ", 16));
+    }
+
+    #[tokio::test]
+    async fn reverse_search_steps_back_through_matches() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        ui.history = vec!["git status".into(), "ls".into(), "git push".into()];
+        ui.input = "draft".to_string();
+        ui.cursor_position = 5;
+        let ctrl_r = KeyEvent::new(KeyCode::Char('r'), KeyModifiers::CONTROL);
+
+        ui.handle_key(ctrl_r, &mut handler).await;
+        for c in "git".chars() {
+            ui.handle_key(KeyEvent::from(KeyCode::Char(c)), &mut handler).await;
+        }
+        assert_eq!(ui.input, "git push");
+        assert!(screen(&ui).contains("(reverse-i-search)`git': git push"));
+        ui.handle_key(ctrl_r, &mut handler).await;
+        assert_eq!(ui.input, "git status");
+        // No older match, so it stays.
+        ui.handle_key(ctrl_r, &mut handler).await;
+        assert_eq!(ui.input, "git status");
+
+        ui.handle_key(KeyEvent::from(KeyCode::Char('x')), &mut handler).await;
+        assert!(screen(&ui).contains("(failed reverse-i-search)`gitx'"));
+        // The history search key is ignored rather than opening the
+        // overlay over the half-done search.
+        ui.handle_key(KeyEvent::new(KeyCode::Char('t'), KeyModifiers::CONTROL), &mut handler).await;
+        assert!(ui.history_search.is_none());
+        ui.handle_key(KeyEvent::from(KeyCode::Esc), &mut handler).await;
+        assert_eq!((ui.input.as_str(), ui.cursor_position), ("draft", 5));
+
+        ui.handle_key(ctrl_r, &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Char('l')), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Enter), &mut handler).await;
+        assert_eq!(ui.input, "ls");
+        assert!(handler.submitted.is_empty());
+        assert!(screen(&ui).contains("> ls"));
+    }
+
+    #[test]
+    fn cursor_sits_after_wide_prompt_and_input() {
+        let mut ui = TerminalUI::new();
+        ui.set_prompt("入力> ".to_string());
+        ui.input = "日本e\u{301}🦀x".to_string();
+        ui.cursor_position = ui.input.len() - 1;
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        terminal.draw(|f| ui.draw(f)).unwrap();
+        // Border, the prompt's 6 cells, then 日本 (4), é (1) and 🦀 (2).
+        assert_eq!(terminal.get_cursor_position().unwrap().x, 1 + 6 + 4 + 1 + 2);
+    }
+
+    #[tokio::test]
+    async fn editing_between_multibyte_characters() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        let mut press = async |code| {
+            ui.handle_key(KeyEvent::from(code), &mut handler).await;
+            ui.input.clone()
+        };
+        for c in "aéßb".chars() {
+            press(KeyCode::Char(c)).await;
+        }
+        press(KeyCode::Left).await;
+        press(KeyCode::Left).await;
+        assert_eq!(press(KeyCode::Char('x')).await, "aéxßb");
+        assert_eq!(press(KeyCode::Backspace).await, "aéßb");
+        assert_eq!(press(KeyCode::Backspace).await, "aßb");
+        press(KeyCode::Right).await;
+        assert_eq!(press(KeyCode::Char('ü')).await, "aßüb");
+        press(KeyCode::Home).await;
+        assert_eq!(press(KeyCode::Backspace).await, "aßüb");
+        press(KeyCode::Right).await;
+        assert_eq!(press(KeyCode::Backspace).await, "ßüb");
+    }
+
+    #[tokio::test]
+    async fn cursor_steps_over_whole_graphemes() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        for c in "a👨\u{200d}👩\u{200d}👧e\u{301}b".chars() {
+            ui.handle_key(KeyEvent::from(KeyCode::Char(c)), &mut handler).await;
+        }
+        ui.handle_key(KeyEvent::from(KeyCode::Left), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Left), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Backspace), &mut handler).await;
+        assert_eq!(ui.input, "ae\u{301}b");
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        terminal.draw(|f| ui.draw(f)).unwrap();
+        // Border, prompt, then the one cell of "a".
+        assert_eq!(terminal.get_cursor_position().unwrap().x, 1 + 2 + 1);
+    }
+
+    #[test]
+    fn incoming_timestamps_are_stripped_when_they_match() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        logger.set_strip_incoming_timestamp(Some("[%H:%M:%S]"));
+        logger.log("[12:30:05] ready\n[later] done".to_string());
+        let bold = Style::default().add_modifier(Modifier::BOLD);
+        logger.log_spans(vec![("[12:30:06] ".to_string(), Style::default()), ("id".to_string(), bold)]);
+        logger.set_strip_incoming_timestamp(None);
+        logger.log("[12:30:07] kept".to_string());
+
+        assert_eq!(texts(&logger), vec!["ready", "[later] done", "id", "[12:30:07] kept"]);
+        assert_eq!(logger.messages.lock().unwrap()[2].segments, vec![(0..2, bold)]);
+
+        logger.set_strip_incoming_timestamp(Some("[%H:%M:%S]"));
+        let id = logger.log_updatable("[12:30:08] 10%").unwrap();
+        assert!(logger.update(id, "[12:30:09] 50%"));
+        assert_eq!(texts(&logger).last().unwrap(), "50%");
+    }
+
+    #[tokio::test]
+    async fn editor_input_spans_numbered_lines() {
+        let mut ui = TerminalUI::new();
+        let mut handler = MockHandler::default();
+        ui.set_input_editor(Some(2));
+        let alt_enter = KeyEvent::new(KeyCode::Enter, KeyModifiers::ALT);
+        for key in [KeyCode::Char('a'), KeyCode::Char('b')] {
+            ui.handle_key(KeyEvent::from(key), &mut handler).await;
+        }
+        ui.handle_key(alt_enter, &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Char('c')), &mut handler).await;
+        ui.handle_key(alt_enter, &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Char('d')), &mut handler).await;
+        assert_eq!(ui.input, "ab\nc\nd");
+
+        // Up stays in the text, keeping the column where it can.
+        ui.handle_key(KeyEvent::from(KeyCode::Up), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::Up), &mut handler).await;
+        ui.handle_key(KeyEvent::from(KeyCode::End), &mut handler).await;
+        assert_eq!(ui.cursor_position, 2);
+
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        terminal.draw(|f| ui.draw(f)).unwrap();
+        let screen: String = terminal.backend().buffer().content.iter().map(|cell| cell.symbol()).collect();
+        assert!(screen.contains("Input · Ln 1, Col 3"));
+        assert!(screen.contains("1 > ab"));
+        assert!(screen.contains("2   c"));
+        // Two of the three lines fit, so the third is scrolled out.
+        assert!(!screen.contains("3   d"));
+        // Border, gutter, prompt, "ab".
+        assert_eq!(terminal.get_cursor_position().unwrap(), (1 + 2 + 2 + 2, 7).into());
+
+        ui.handle_key(KeyEvent::from(KeyCode::Enter), &mut handler).await;
+        assert_eq!(handler.submitted, vec!["ab\nc\nd"]);
+    }
+
+    #[test]
+    fn help_descriptions_line_up_after_wide_keys() {
+        let mut ui = TerminalUI::new();
+        ui.set_history_search_key(KeyCode::Char('検'), KeyModifiers::CONTROL);
+        ui.show_help = true;
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(80, 30)).unwrap();
+        terminal.draw(|f| ui.draw(f)).unwrap();
+
+        let buffer = terminal.backend().buffer();
+        // The cell where the description starts on the row for `key`.
+        let column = |key: &str, description: &str| {
+            (0..30).find_map(|y| {
+                let cells: Vec<&str> = (0..80).map(|x| buffer[(x, y)].symbol()).collect();
+                if !cells.concat().contains(key) {
+                    return None;
+                }
+                (0..cells.len()).find(|&x| cells[x..].concat().starts_with(description))
+            }).unwrap()
+        };
+        assert_eq!(column("Ctrl+検", "Search history"), column("Enter", "Submit command"));
+    }
+
+    #[test]
+    fn aligned_lines_sit_centered_or_right() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        logger.log_aligned("mid".to_string(), Alignment::Center);
+        logger.log_aligned("end".to_string(), Alignment::Right);
+
+        let screen: Vec<char> = screen(&ui).chars().collect();
+        let rows: Vec<String> = screen.chunks(40).map(|r| r.iter().collect()).collect();
+        let column = |word: &str| rows.iter().find_map(|r| r.find(word).map(|i| r[..i].chars().count())).unwrap();
+        // Inside the border, the pane is 38 columns wide.
+        assert_eq!(column("mid"), 1 + (38 - 3) / 2);
+        assert_eq!(column("end"), 1 + 38 - 3);
+    }
+
+    #[test]
+    fn diagnostics_render_on_any_width() {
+        let mut ui = TerminalUI::new();
+        ui.set_word_wrap(true);
+        ui.get_message_logger().run_diagnostics();
+        for width in [10, 40, 120] {
+            let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(width, 20)).unwrap();
+            terminal.draw(|f| ui.draw(f)).unwrap();
+        }
+        assert!(screen(&ui).contains("End of diagnostics"));
+    }
+
+    // The text on the bottom row of the message pane.
+    fn bottom_row(ui: &TerminalUI) -> String {
+        let screen = screen(ui);
+        let rows: Vec<String> = screen.chars().collect::<Vec<_>>().chunks(40).map(|r| r.iter().collect()).collect();
+        // Two input rows and the input's top border below, then the pane's
+        // bottom border.
+        rows[rows.len() - 5].trim_matches(|c| c == '│' || c == ' ').to_string()
+    }
+
+    #[test]
+    fn scrolled_view_stays_put_while_old_lines_are_evicted() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        for i in 0..MAX_MESSAGES {
+            logger.log(format!("line {}", i));
+        }
+        screen(&ui);
+        logger.scroll_by(10);
+        assert_eq!(bottom_row(&ui), "line 989");
+
+        for i in 0..5 {
+            logger.log(format!("new {}", i));
+        }
+        assert_eq!(bottom_row(&ui), "line 989");
+    }
+
+    #[test]
+    fn scrolled_view_stays_put_when_a_fold_below_it_closes() {
+        let ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        for i in 0..20 {
+            logger.log(format!("line {}", i));
+        }
+        let id = logger.fold_begin("trace").unwrap();
+        for i in 0..5 {
+            logger.log(format!("frame {}", i));
+        }
+        logger.fold_end();
+        logger.set_fold_expanded(id, true);
+        screen(&ui);
+        logger.scroll_by(8);
+        assert_eq!(bottom_row(&ui), "line 17");
+
+        logger.set_fold_expanded(id, false);
+        assert_eq!(bottom_row(&ui), "line 17");
+    }
+
+    #[test]
+    fn scrolled_view_stays_put_when_the_level_filter_changes() {
+        let mut ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        for i in 0..20 {
+            logger.info(&format!("line {}", i));
+            logger.debug(&format!("detail {}", i));
+        }
+        screen(&ui);
+        logger.scroll_by(9);
+        assert_eq!(bottom_row(&ui), "[INFO] line 15");
+
+        ui.set_min_level(Some(Level::Info));
+        assert_eq!(bottom_row(&ui), "[INFO] line 15");
+        ui.set_min_level(None);
+        assert_eq!(bottom_row(&ui), "[INFO] line 15");
+    }
+
+    #[test]
+    fn focused_error_stays_on_screen_with_a_level_filter() {
+        let mut ui = TerminalUI::new();
+        let logger = ui.get_message_logger();
+        ui.set_auto_focus_errors(true);
+        ui.set_min_level(Some(Level::Info));
+        for i in 0..10 {
+            logger.info(&format!("before {}", i));
+        }
+        screen(&ui);
+
+        logger.error("boom");
+        ui.follow_error();
+        for i in 0..20 {
+            logger.info(&format!("line {}", i));
+            logger.debug(&format!("detail {}", i));
+        }
+        ui.follow_error();
+        let screen = screen(&ui);
+        let rows: Vec<String> = screen.chars().collect::<Vec<_>>().chunks(40).map(|r| r.iter().collect()).collect();
+        assert!(rows[1].contains("[ERROR] boom"), "{:?}", rows);
+    }
+
+    #[test]
+    fn frames_are_spaced_by_the_fps_cap() {
+        let mut ui = TerminalUI::new();
+        assert_eq!(ui.until_next_frame(), Duration::ZERO);
+
+        ui.set_max_fps(Some(10));
+        ui.last_frame = Some(Instant::now());
+        let wait = ui.until_next_frame();
+        assert!(wait > Duration::from_millis(50) && wait <= Duration::from_millis(100));
+
+        ui.set_max_fps(None);
+        assert_eq!(ui.until_next_frame(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn heartbeat_fires_from_the_loop() {
+        let beats = std::rc::Rc::new(std::cell::Cell::new(0));
+        let mut ui = TerminalUI::new();
+        let counter = beats.clone();
+        ui.set_heartbeat(Duration::ZERO, move || counter.set(counter.get() + 1));
+
+        let mut events = ScriptedEvents(VecDeque::from([
+            Event::Key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+        ]));
+        let mut terminal = Terminal::new(ratatui::backend::TestBackend::new(40, 10)).unwrap();
+        ui.run_on(&mut terminal, &mut events, &mut MockHandler::default()).await.unwrap();
+        assert_eq!(beats.get(), 1);
+    }
+}
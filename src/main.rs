@@ -1,6 +1,4 @@
-mod core;
-
-use crate::core::repl_new::Terminal;
+use riege_xterm::core::repl_new::Terminal;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {